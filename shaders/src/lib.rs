@@ -32,28 +32,70 @@ pub struct Glyph {
     atlas_y: u32,
     pos_x: u32,
     pos_y: u32,
+    style: u32,
 }
 
-#[spirv(compute(threads(5, 11)))]
+/// Pushed by the renderer for each dispatch.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Constants {
+    /// Width and height of a glyph in pixels, both of the tiles in the atlas and of the cells on
+    /// screen, so any font can be drawn.
+    width: u32,
+    height: u32,
+    /// Index of the glyph the first workgroup along x draws. There's a limit to how many
+    /// workgroups fit in a dispatch, so a frame with more glyphs than that takes several.
+    first_glyph: u32,
+}
+
+/// Each workgroup draws a square this many pixels across of one glyph, and as many are
+/// dispatched along y and z as it takes to cover the glyph. Matches the renderer.
+const GROUP_SIZE: u32 = 8;
+
+// Glyph styles, matching the renderer
+const STYLE_CURSOR: u32 = 1;
+const STYLE_SELECTED: u32 = 2;
+const STYLE_MATCH: u32 = 3;
+const STYLE_PANEL: u32 = 4;
+const STYLE_PANEL_SELECTED: u32 = 5;
+const STYLE_PANEL_MATCH: u32 = 6;
+
+#[spirv(compute(threads(8, 8)))]
 pub fn cs_with_font(
     #[spirv(workgroup_id)] id: glam::UVec3,
     #[spirv(local_invocation_id)] local_id: glam::UVec3,
+    #[spirv(push_constant)] constants: &Constants,
     #[spirv(descriptor_set = 0, binding = 0)] fb: &Image!(2D, format=rgba32f, sampled=false),
     #[spirv(descriptor_set = 0, binding = 1)] atlas: &Image!(2D, format=r8ui, sampled=false),
     #[spirv(descriptor_set = 0, binding = 2, storage_buffer)] data: &[Glyph],
 ) {
-    let our_glyph = &data[id.x as usize];
-    let atlas_entry = glam::uvec2(our_glyph.atlas_x, our_glyph.atlas_y) * glam::uvec2(5, 11);
+    let local_coords = glam::uvec2(id.y, id.z) * GROUP_SIZE + glam::uvec2(local_id.x, local_id.y);
+    // The last workgroups hang over the edge of the glyph unless it's a multiple of the size
+    if local_coords.x >= constants.width || local_coords.y >= constants.height {
+        return;
+    }
+
+    let size = glam::uvec2(constants.width, constants.height);
+    let our_glyph = &data[(constants.first_glyph + id.x) as usize];
+    let atlas_entry = glam::uvec2(our_glyph.atlas_x, our_glyph.atlas_y) * size;
 
-    let global_coords = glam::uvec2(our_glyph.pos_x, our_glyph.pos_y) * glam::uvec2(5, 11);
-    let local_coords = glam::uvec2(local_id.x, local_id.y);
+    let global_coords = glam::uvec2(our_glyph.pos_x, our_glyph.pos_y) * size;
 
     let read_coords = atlas_entry + local_coords;
     let write_coords = global_coords + local_coords;
 
     let atlas_px: glam::UVec4 = atlas.read(read_coords);
     // let color = (atlas_px.x as f32 / 255.0) * glam::vec3(0.0, 0.8, 1.0);
-    let color = (atlas_px.x as f32 / 255.0) * glam::vec3(1.0, 1.0, 1.0);
+    let (fg, bg) = match our_glyph.style {
+        STYLE_CURSOR => (glam::vec3(0.0, 0.0, 0.0), glam::vec3(1.0, 1.0, 1.0)),
+        STYLE_SELECTED => (glam::vec3(1.0, 1.0, 1.0), glam::vec3(0.2, 0.3, 0.5)),
+        STYLE_MATCH => (glam::vec3(1.0, 1.0, 1.0), glam::vec3(0.4, 0.3, 0.1)),
+        STYLE_PANEL => (glam::vec3(0.9, 0.9, 0.9), glam::vec3(0.12, 0.12, 0.16)),
+        STYLE_PANEL_SELECTED => (glam::vec3(1.0, 1.0, 1.0), glam::vec3(0.2, 0.3, 0.5)),
+        STYLE_PANEL_MATCH => (glam::vec3(1.0, 0.8, 0.3), glam::vec3(0.12, 0.12, 0.16)),
+        _ => (glam::vec3(1.0, 1.0, 1.0), glam::vec3(0.0, 0.0, 0.0)),
+    };
+    let color = bg.lerp(fg, atlas_px.x as f32 / 255.0);
     unsafe {
         fb.write(write_coords, color);
     }
@@ -0,0 +1,917 @@
+mod diff;
+mod document;
+mod encoding;
+mod file;
+mod history;
+mod motion;
+mod object;
+pub mod search;
+mod workspace;
+
+pub use document::Document;
+pub use encoding::Encoding;
+pub use file::{Format, LineEnding};
+pub use history::RevisionInfo;
+pub use search::{ReplacedLine, SearchStatus};
+pub use workspace::GrepEvent;
+
+use crate::{Command, Direction, Resolution, Selections, ServerMessage};
+use crossbeam_channel::{Receiver, Sender};
+use search::Matcher;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use workspace::{Grep, Hit};
+
+// The editor is the server side of the client/server split: clients send `Command`s, the editor
+// applies them to its documents and answers with whatever view state changed.
+
+/// How often to look for files changed by other programs, see `Editor::check_files`.
+pub const CHECK_FILES_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identifies an open document for as long as it stays open. Ids aren't reused.
+pub type DocumentId = usize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferInfo {
+    pub id: DocumentId,
+    pub name: String,
+    pub modified: bool,
+    /// Lists what a project-wide search found, for `Command::OpenResult` to open.
+    pub results: bool,
+}
+
+/// A project-wide search and the buffer listing what it found, a line per hit.
+struct Results {
+    grep: Grep,
+    document: DocumentId,
+    hits: Vec<Hit>,
+}
+
+pub struct Editor {
+    /// Ordered by id, so in the order they were opened.
+    documents: BTreeMap<DocumentId, Document>,
+    current: DocumentId,
+    next_id: DocumentId,
+    page_height: usize,
+    /// Last search, for `Command::SearchNext`.
+    search: Option<(String, Direction)>,
+    /// Selections from before the search being typed, to search from and to go back to.
+    search_origin: Option<Selections>,
+    /// Where project-wide searches look, the directory the editor was started in.
+    root: PathBuf,
+    results: Option<Results>,
+    next_grep: usize,
+    grep_tx: Sender<GrepEvent>,
+    grep_rx: Receiver<GrepEvent>,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let (grep_tx, grep_rx) = crossbeam_channel::unbounded();
+        Self {
+            documents: BTreeMap::from([(0, Document::new())]),
+            current: 0,
+            next_id: 1,
+            page_height: 40,
+            search: None,
+            search_origin: None,
+            root: std::env::current_dir().unwrap_or_default(),
+            results: None,
+            next_grep: 0,
+            grep_tx,
+            grep_rx,
+        }
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.documents[&self.current]
+    }
+
+    fn document_mut(&mut self) -> &mut Document {
+        self.documents.get_mut(&self.current).unwrap()
+    }
+
+    pub fn buffers(&self) -> Vec<BufferInfo> {
+        self.documents
+            .iter()
+            .map(|(&id, document)| BufferInfo {
+                id,
+                name: document.name(),
+                modified: document.is_modified(),
+                results: matches!(&self.results, Some(results) if results.document == id),
+            })
+            .collect()
+    }
+
+    pub fn execute(&mut self, command: Command) -> Vec<ServerMessage> {
+        if command.edits() && self.document().is_read_only() {
+            let error = format!("{} is read-only", self.document().name());
+            return vec![ServerMessage::Error(error)];
+        }
+
+        let mut replies = Vec::new();
+        let current = self.current;
+        let selections = self.document().selections().clone();
+
+        // Only typing extends the current undo group
+        if !matches!(command, Command::Insert(_) | Command::NewLine) {
+            self.document_mut().commit();
+        }
+        // Anything else ends a search being typed where it is
+        if !matches!(
+            command,
+            Command::Search { .. } | Command::SearchPreview { .. } | Command::CancelSearch
+        ) {
+            self.search_origin = None;
+        }
+
+        match command {
+            Command::Open { path, position } => replies.extend(self.open(&path, position)),
+            Command::ReopenWithEncoding(encoding) => {
+                let document = self.document_mut();
+                if document.is_modified() {
+                    replies.push(ServerMessage::Error("buffer has unsaved changes".into()));
+                } else if let Err(e) = document.reload(Some(encoding)) {
+                    replies.push(ServerMessage::Error(format!("{:#}", e)));
+                } else {
+                    replies.push(ServerMessage::Status(format!("reopened as {}", encoding)));
+                }
+            }
+            Command::Save => {
+                let document = self.document_mut();
+                match document.save() {
+                    Ok(()) => replies.push(ServerMessage::Status(format!(
+                        "wrote {}",
+                        document.path().unwrap().display()
+                    ))),
+                    Err(e) => replies.push(ServerMessage::Error(format!("{:#}", e))),
+                }
+            }
+            Command::SaveAs(path) => match self.document_mut().save_as(&path) {
+                Ok(()) => replies.push(ServerMessage::Status(format!("wrote {}", path))),
+                Err(e) => replies.push(ServerMessage::Error(format!("{:#}", e))),
+            },
+            Command::ResolveExternalChange { path, resolution } => {
+                match self.find(Path::new(&path)) {
+                    Some(id) => replies.extend(self.resolve(id, resolution)),
+                    None => replies.push(ServerMessage::Error(format!("{} isn't open", path))),
+                }
+            }
+            Command::NextBuffer => {
+                let mut after = self.documents.range(self.current + 1..);
+                let (&id, _) = after
+                    .next()
+                    .or_else(|| self.documents.iter().next())
+                    .unwrap();
+                self.current = id;
+            }
+            Command::PreviousBuffer => {
+                let mut before = self.documents.range(..self.current);
+                let (&id, _) = before
+                    .next_back()
+                    .or_else(|| self.documents.iter().next_back())
+                    .unwrap();
+                self.current = id;
+            }
+            Command::SwitchBuffer(name) => match self.find_by_name(&name) {
+                Ok(id) => self.current = id,
+                Err(e) => replies.push(ServerMessage::Error(format!("{:#}", e))),
+            },
+            Command::CloseBuffer { force } => {
+                if self.document().is_modified() && !force {
+                    replies.push(ServerMessage::Error(format!(
+                        "{} has unsaved changes",
+                        self.document().name()
+                    )));
+                } else {
+                    self.close(self.current);
+                }
+            }
+            Command::Buffers => replies.push(self.buffer_list()),
+            Command::Quit { force } => {
+                let unsaved: Vec<_> = self
+                    .buffers()
+                    .into_iter()
+                    .filter(|buffer| buffer.modified)
+                    .collect();
+                if unsaved.is_empty() || force {
+                    replies.push(ServerMessage::Quit);
+                } else {
+                    replies.push(ServerMessage::ConfirmQuit { unsaved });
+                }
+            }
+            Command::Select(scope) => self.document_mut().select_object(scope),
+            Command::SelectTo(motion, count) => {
+                let page_height = self.page_height;
+                self.document_mut().select_to(motion, count, page_height);
+            }
+            Command::Move(motion) => {
+                let page_height = self.page_height;
+                self.document_mut().move_cursor(motion, page_height);
+            }
+            Command::SetPageHeight(lines) => self.page_height = lines.max(1),
+            Command::GrowLines(direction) => self.document_mut().grow_lines(direction),
+            Command::AddCursor(direction) => self.document_mut().add_cursor(direction),
+            Command::SplitLines => self.document_mut().split_lines(),
+            Command::SelectMatches(pattern) => {
+                if let Err(e) = self.document_mut().select_matches(&pattern) {
+                    replies.push(ServerMessage::Error(format!("{:#}", e)));
+                }
+            }
+            Command::KeepPrimary => self.document_mut().keep_primary(),
+            Command::Insert(c) => self.document_mut().insert(c),
+            Command::Delete => self.document_mut().delete(),
+            Command::DeleteSelection => self.document_mut().delete_selection(),
+            Command::DeleteWordBackward => self.document_mut().delete_word_backward(),
+            Command::NewLine => self.document_mut().insert('\n'),
+            Command::OpenLine(direction) => self.document_mut().open_line(direction),
+            Command::Search { pattern, direction } => {
+                if let Some(origin) = self.search_origin.take() {
+                    self.document_mut().set_selections(origin);
+                }
+                self.search = Some((pattern, direction));
+                replies.extend(self.search_next(Direction::Forward));
+            }
+            Command::SearchPreview { pattern, direction } => {
+                replies.push(self.search_preview(&pattern, direction))
+            }
+            Command::CancelSearch => {
+                if let Some(origin) = self.search_origin.take() {
+                    self.document_mut().set_selections(origin);
+                }
+                replies.push(ServerMessage::Search(None));
+            }
+            Command::SearchNext(direction) => replies.extend(self.search_next(direction)),
+            Command::Grep(pattern) => replies.push(self.grep(&pattern)),
+            Command::OpenResult => replies.extend(self.open_result()),
+            Command::ListFiles => replies.push(self.list_files()),
+            Command::Replace {
+                pattern,
+                replacement,
+            } => match self.document().replacements(&pattern, &replacement) {
+                Ok(replacements) if replacements.is_empty() => replies.push(ServerMessage::Error(
+                    format!("pattern not found: {}", pattern),
+                )),
+                Ok(replacements) => {
+                    let count = replacements.len();
+                    self.document_mut().replace_all(replacements);
+                    replies.push(ServerMessage::Status(format!(
+                        "replaced {} match{}",
+                        count,
+                        if count == 1 { "" } else { "es" }
+                    )));
+                }
+                Err(e) => replies.push(ServerMessage::Error(format!("{:#}", e))),
+            },
+            Command::ReplacePreview {
+                pattern,
+                replacement,
+            } => match self.document().replacements(&pattern, &replacement) {
+                Ok(replacements) => replies.push(ServerMessage::ReplacePreview(search::preview(
+                    self.document().text(),
+                    &replacements,
+                ))),
+                Err(e) => replies.push(ServerMessage::Error(format!("{:#}", e))),
+            },
+            Command::Undo => {
+                if !self.document_mut().undo() {
+                    replies.push(ServerMessage::Status("already at oldest change".into()));
+                }
+            }
+            Command::Redo => {
+                if !self.document_mut().redo() {
+                    replies.push(ServerMessage::Status("already at newest change".into()));
+                }
+            }
+            Command::Earlier(step) => {
+                if !self.document_mut().earlier(step) {
+                    replies.push(ServerMessage::Status("already at oldest change".into()));
+                }
+            }
+            Command::Later(step) => {
+                if !self.document_mut().later(step) {
+                    replies.push(ServerMessage::Status("already at newest change".into()));
+                }
+            }
+            Command::Revision(revision) => {
+                if !self.document_mut().goto_revision(revision) {
+                    replies.push(ServerMessage::Status(format!("no revision {}", revision)));
+                }
+            }
+            Command::History => {
+                let history = self.document().history();
+                replies.push(ServerMessage::History {
+                    revisions: history.revisions(),
+                    current: history.current(),
+                });
+            }
+        }
+
+        if self.current != current || !self.documents.contains_key(&current) {
+            // Pending changes are to text the client isn't showing anymore
+            self.flush_changes();
+            replies.push(self.snapshot());
+            replies.push(self.buffer_list());
+            return replies;
+        }
+
+        replies.extend(self.flush_changes());
+        if *self.document().selections() != selections {
+            replies.push(ServerMessage::Selections(
+                self.document().selections().clone(),
+            ));
+        }
+
+        replies
+    }
+
+    /// Opens the file at `path`, or switches to it if it's open, moving the cursor to
+    /// `position`.
+    fn open(&mut self, path: &str, position: Option<(usize, usize)>) -> Vec<ServerMessage> {
+        let mut replies = Vec::new();
+        match self.find(Path::new(path)) {
+            Some(id) => self.current = id,
+            None => match Document::open(path) {
+                Ok(document) => {
+                    let format = document.format();
+                    replies.push(ServerMessage::Status(format!(
+                        "{} ({} lines, {}{}, {:?})",
+                        path,
+                        document.text().len_lines(),
+                        format.encoding,
+                        if format.bom { " with bom" } else { "" },
+                        format.line_ending,
+                    )));
+                    self.remove_placeholder();
+                    self.current = self.add(document);
+                }
+                Err(e) => {
+                    replies.push(ServerMessage::Error(format!(
+                        "failed to open {}: {:#}",
+                        path, e
+                    )));
+                    return replies;
+                }
+            },
+        }
+        if let Some((line, column)) = position {
+            self.document_mut().goto(line, column);
+        }
+        replies
+    }
+
+    /// Selects the next match of the last search. `Backward` searches the opposite way to how
+    /// the search was started.
+    fn search_next(&mut self, direction: Direction) -> Vec<ServerMessage> {
+        let (pattern, search_direction) = match &self.search {
+            Some(search) => search.clone(),
+            None => return vec![ServerMessage::Error("no previous search".into())],
+        };
+        let direction = match direction {
+            Direction::Forward => search_direction,
+            Direction::Backward => search_direction.reverse(),
+        };
+
+        let mut matcher = match Matcher::new(&pattern) {
+            Ok(matcher) => matcher,
+            Err(e) => return vec![ServerMessage::Error(format!("{:#}", e))],
+        };
+        let document = self.document_mut();
+        let from = document.selection().start();
+        match matcher.find(document.text(), from, direction) {
+            Some((range, wrapped)) => {
+                document.select(range.clone());
+                let status = matcher.status(document.text(), range);
+                let mut replies = vec![ServerMessage::Search(Some(status))];
+                if wrapped {
+                    replies.push(ServerMessage::Status("search wrapped".into()));
+                }
+                replies
+            }
+            None => vec![ServerMessage::Error(format!(
+                "pattern not found: {}",
+                pattern
+            ))],
+        }
+    }
+
+    /// Selects the first match of `pattern` from where the cursor was when the search began,
+    /// or goes back there if there isn't one.
+    fn search_preview(&mut self, pattern: &str, direction: Direction) -> ServerMessage {
+        let document = self.documents.get_mut(&self.current).unwrap();
+        let origin = self
+            .search_origin
+            .get_or_insert_with(|| document.selections().clone());
+        document.set_selections(origin.clone());
+        if pattern.is_empty() {
+            return ServerMessage::Search(None);
+        }
+
+        // Likely just not finished typing
+        let mut matcher = match Matcher::new(pattern) {
+            Ok(matcher) => matcher,
+            Err(_) => {
+                return ServerMessage::Search(Some(SearchStatus {
+                    pattern: pattern.to_string(),
+                    current: None,
+                    total: 0,
+                }))
+            }
+        };
+        let from = document.selection().start();
+        let status = match matcher.find(document.text(), from, direction) {
+            Some((range, _)) => {
+                document.select(range.clone());
+                matcher.status(document.text(), range)
+            }
+            None => matcher.status(document.text(), 0..0),
+        };
+        ServerMessage::Search(Some(status))
+    }
+
+    /// Starts searching the project for `pattern`, replacing any search still going and its
+    /// results buffer with a new one.
+    fn grep(&mut self, pattern: &str) -> ServerMessage {
+        let id = self.next_grep;
+        let grep = match workspace::grep(self.root.clone(), pattern, id, self.grep_tx.clone()) {
+            Ok(grep) => grep,
+            Err(e) => return ServerMessage::Error(format!("{:#}", e)),
+        };
+        self.next_grep += 1;
+
+        if let Some(results) = self.results.take() {
+            self.documents.remove(&results.document);
+        }
+        if self.documents.contains_key(&self.current) {
+            self.remove_placeholder();
+        }
+        let mut results = Document::titled(format!("[grep {}]", pattern));
+        results.set_read_only(true);
+        let document = self.add(results);
+        self.current = document;
+        self.results = Some(Results {
+            grep,
+            document,
+            hits: Vec::new(),
+        });
+        ServerMessage::Status(format!("searching for {}", pattern))
+    }
+
+    /// Results of project-wide searches, for the main loop to pass to `handle_grep` as they
+    /// arrive.
+    pub fn grep_events(&self) -> Receiver<GrepEvent> {
+        self.grep_rx.clone()
+    }
+
+    /// Adds what a project-wide search found to its results buffer.
+    pub fn handle_grep(&mut self, event: GrepEvent) -> Vec<ServerMessage> {
+        let results = match &mut self.results {
+            Some(results) if self.documents.contains_key(&results.document) => results,
+            _ => return Vec::new(),
+        };
+        match event {
+            GrepEvent::Found { id, hits } if id == results.grep.id => {
+                let mut lines = String::new();
+                for hit in &hits {
+                    let path = hit.path.strip_prefix(&self.root).unwrap_or(&hit.path);
+                    // Anything that would break the line would throw off which hit is where
+                    let text: String = hit
+                        .text
+                        .chars()
+                        .map(
+                            |c| match c.is_control() || matches!(c, '\u{2028}' | '\u{2029}') {
+                                true => ' ',
+                                false => c,
+                            },
+                        )
+                        .collect();
+                    let _ = writeln!(
+                        lines,
+                        "{}:{}:{}: {}",
+                        path.display(),
+                        hit.line + 1,
+                        hit.column + 1,
+                        text
+                    );
+                }
+                results.hits.extend(hits);
+                let document = results.document;
+                self.documents.get_mut(&document).unwrap().append(&lines);
+                self.flush_changes()
+            }
+            GrepEvent::Done { id, files } if id == results.grep.id => {
+                vec![ServerMessage::Status(format!(
+                    "{} results, {} files searched",
+                    results.hits.len(),
+                    files
+                ))]
+            }
+            // From a search that's been replaced
+            _ => Vec::new(),
+        }
+    }
+
+    /// Opens the file of the hit on the cursor's line of the results buffer, at the hit.
+    fn open_result(&mut self) -> Vec<ServerMessage> {
+        let hit = match &self.results {
+            Some(results) if results.document == self.current => {
+                let document = self.document();
+                let line = document.text().char_to_line(document.selection().head);
+                match results.hits.get(line) {
+                    Some(hit) => hit.clone(),
+                    None => return Vec::new(),
+                }
+            }
+            _ => return vec![ServerMessage::Error("not in a results buffer".into())],
+        };
+        self.open(
+            &hit.path.display().to_string(),
+            Some((hit.line, hit.column)),
+        )
+    }
+
+    /// Every file under the root, relative to it. Walking the tree is quick enough next to
+    /// searching it to not need a thread.
+    fn list_files(&self) -> ServerMessage {
+        let files = workspace::files(&self.root)
+            .filter_map(|path| Some(path.strip_prefix(&self.root).ok()?.display().to_string()))
+            .collect();
+        ServerMessage::Files {
+            root: self.root.display().to_string(),
+            files,
+        }
+    }
+
+    /// Removes the current document if it's an untouched scratch buffer, which is just a
+    /// placeholder, before switching to another.
+    fn remove_placeholder(&mut self) {
+        let document = self.document();
+        if document.path().is_none() && !document.is_modified() && !self.is_results(self.current) {
+            self.documents.remove(&self.current);
+        }
+    }
+
+    fn is_results(&self, id: DocumentId) -> bool {
+        matches!(&self.results, Some(results) if results.document == id)
+    }
+
+    fn add(&mut self, document: Document) -> DocumentId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.documents.insert(id, document);
+        id
+    }
+
+    /// Closes a document, switching to the next one. Closing the last document leaves an empty
+    /// scratch buffer, so there's always a current document.
+    fn close(&mut self, id: DocumentId) {
+        self.documents.remove(&id);
+        if self.is_results(id) {
+            // Stops the search too
+            self.results = None;
+        }
+        if self.current == id {
+            let next = self.documents.range(id..).next().map(|(&id, _)| id);
+            self.current = match next.or_else(|| self.documents.keys().next_back().copied()) {
+                Some(id) => id,
+                None => self.add(Document::new()),
+            };
+        }
+    }
+
+    /// Finds a document by id, file name or path, or failing that a unique part of its path.
+    fn find_by_name(&self, name: &str) -> anyhow::Result<DocumentId> {
+        if let Ok(id) = name.parse() {
+            if self.documents.contains_key(&id) {
+                return Ok(id);
+            }
+        }
+        if let Some(id) = self.find(Path::new(name)) {
+            return Ok(id);
+        }
+        if let Some((&id, _)) = self.documents.iter().find(|(_, d)| d.name() == name) {
+            return Ok(id);
+        }
+
+        let matches: Vec<_> = self
+            .documents
+            .iter()
+            .filter(|(_, d)| matches!(d.path(), Some(p) if p.to_string_lossy().contains(name)))
+            .map(|(&id, _)| id)
+            .collect();
+        match matches[..] {
+            [id] => Ok(id),
+            [] => anyhow::bail!("no buffer matches {}", name),
+            _ => anyhow::bail!("{} matches {} buffers", name, matches.len()),
+        }
+    }
+
+    /// Looks for documents whose files were changed by something else. Clean documents are
+    /// reloaded; for ones with unsaved changes the client is asked what to do, once per change.
+    pub fn check_files(&mut self) -> Vec<ServerMessage> {
+        let mut replies = Vec::new();
+        let selections = self.document().selections().clone();
+
+        for document in self.documents.values_mut() {
+            if document.has_conflict() || !document.changed_on_disk() {
+                continue;
+            }
+            let path = document.path().unwrap().display().to_string();
+            if document.is_modified() {
+                document.mark_conflict();
+                replies.push(ServerMessage::FileChanged { path });
+            } else {
+                match document.reload(None) {
+                    Ok(()) => replies.push(ServerMessage::Status(format!("reloaded {}", path))),
+                    Err(e) => replies.push(ServerMessage::Error(format!(
+                        "failed to reload {}: {:#}",
+                        path, e
+                    ))),
+                }
+            }
+        }
+
+        replies.extend(self.flush_changes());
+        if *self.document().selections() != selections {
+            replies.push(ServerMessage::Selections(
+                self.document().selections().clone(),
+            ));
+        }
+        replies
+    }
+
+    fn find(&self, path: &Path) -> Option<DocumentId> {
+        let path = path.canonicalize().ok()?;
+        self.documents
+            .iter()
+            .find(|(_, document)| {
+                matches!(document.path().map(Path::canonicalize), Some(Ok(p)) if p == path)
+            })
+            .map(|(&id, _)| id)
+    }
+
+    fn resolve(&mut self, id: DocumentId, resolution: Resolution) -> Vec<ServerMessage> {
+        let document = self.documents.get_mut(&id).unwrap();
+        let path = document.path().unwrap().display().to_string();
+        match resolution {
+            Resolution::Reload => match document.reload(None) {
+                Ok(()) => vec![ServerMessage::Status(format!("reloaded {}", path))],
+                Err(e) => vec![ServerMessage::Error(format!("{:#}", e))],
+            },
+            Resolution::Keep => {
+                document.keep_buffer();
+                vec![ServerMessage::Status(format!(
+                    "keeping buffer, saving will overwrite {}",
+                    path
+                ))]
+            }
+            Resolution::Diff => match document.diff_with_disk() {
+                Ok(diff) => vec![ServerMessage::Diff { path, diff }],
+                Err(e) => vec![ServerMessage::Error(format!("{:#}", e))],
+            },
+        }
+    }
+
+    /// Turns the current document's pending changes into deltas. Other documents aren't shown,
+    /// so their changes are dropped; clients get a snapshot when switching to them.
+    fn flush_changes(&mut self) -> Vec<ServerMessage> {
+        let mut replies = Vec::new();
+        for (&id, document) in self.documents.iter_mut() {
+            let changes = document.take_changes();
+            if id == self.current {
+                replies.extend(changes.into_iter().map(|change| ServerMessage::Delta {
+                    range: change.range,
+                    text: change.text,
+                }));
+            }
+        }
+        replies
+    }
+
+    pub fn buffer_list(&self) -> ServerMessage {
+        ServerMessage::Buffers {
+            buffers: self.buffers(),
+            current: self.current,
+        }
+    }
+
+    pub fn snapshot(&self) -> ServerMessage {
+        let document = self.document();
+        ServerMessage::Snapshot {
+            text: document.text().to_string(),
+            selections: document.selections().clone(),
+        }
+    }
+}
+
+#[test]
+fn external_changes() {
+    let path = std::env::temp_dir().join(format!("kavi-external-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let name = path.display().to_string();
+
+    let mut editor = Editor::new();
+    editor.execute(Command::Open {
+        path: name.clone(),
+        position: None,
+    });
+    assert!(editor.check_files().is_empty());
+
+    // Clean buffers follow the file
+    std::fs::write(&path, "one\ntwo\n").unwrap();
+    editor.check_files();
+    assert_eq!(editor.document().text(), "one\ntwo\n");
+
+    // Dirty ones ask, once, and refuse to save over the file
+    editor.execute(Command::Insert('!'));
+    std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+    let replies = editor.check_files();
+    assert!(matches!(&replies[..], [ServerMessage::FileChanged { path }] if *path == name));
+    assert!(editor.check_files().is_empty());
+    assert!(editor.document_mut().save().is_err());
+
+    let replies = editor.execute(Command::ResolveExternalChange {
+        path: name.clone(),
+        resolution: Resolution::Diff,
+    });
+    assert!(matches!(&replies[..], [ServerMessage::Diff { diff, .. }] if diff.contains("-three")));
+
+    editor.execute(Command::ResolveExternalChange {
+        path: name.clone(),
+        resolution: Resolution::Keep,
+    });
+    editor.execute(Command::Save);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "!one\ntwo\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn buffers() {
+    let dir = std::env::temp_dir();
+    let paths: Vec<_> = ["a", "b"]
+        .iter()
+        .map(|name| dir.join(format!("kavi-buffer-{}-{}.txt", std::process::id(), name)))
+        .collect();
+    for path in &paths {
+        std::fs::write(path, "text\n").unwrap();
+    }
+    let name = |i: usize| paths[i].file_name().unwrap().to_string_lossy().into_owned();
+
+    // The initial scratch buffer makes way for the first file
+    let mut editor = Editor::new();
+    for path in &paths {
+        editor.execute(Command::Open {
+            path: path.display().to_string(),
+            position: None,
+        });
+    }
+    let ids: Vec<_> = editor.buffers().iter().map(|b| b.id).collect();
+    assert_eq!(ids, [1, 2]);
+    assert_eq!(editor.document().name(), name(1));
+
+    editor.execute(Command::NextBuffer);
+    assert_eq!(editor.document().name(), name(0));
+    editor.execute(Command::PreviousBuffer);
+    assert_eq!(editor.document().name(), name(1));
+    editor.execute(Command::SwitchBuffer("-a.txt".to_string()));
+    assert_eq!(editor.document().name(), name(0));
+
+    // Reopening switches to the existing buffer
+    editor.execute(Command::Open {
+        path: paths[1].display().to_string(),
+        position: None,
+    });
+    assert_eq!(editor.buffers().len(), 2);
+
+    editor.execute(Command::Insert('!'));
+    let replies = editor.execute(Command::Quit { force: false });
+    assert!(matches!(&replies[..], [ServerMessage::ConfirmQuit { unsaved }] if unsaved[0].id == 2));
+    let replies = editor.execute(Command::CloseBuffer { force: false });
+    assert!(matches!(replies[..], [ServerMessage::Error(_)]));
+
+    editor.execute(Command::CloseBuffer { force: true });
+    assert_eq!(editor.document().name(), name(0));
+    editor.execute(Command::CloseBuffer { force: false });
+    assert_eq!(editor.document().name(), "[scratch]");
+    let replies = editor.execute(Command::Quit { force: false });
+    assert!(matches!(replies[..], [ServerMessage::Quit]));
+
+    for path in &paths {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn incremental_search() {
+    let mut editor = Editor::new();
+    for c in "one two\ntwo one".chars() {
+        editor.execute(Command::Insert(c));
+    }
+    editor.execute(Command::Move(crate::Motion::DocumentStart));
+    let preview = |editor: &mut Editor, pattern: &str| {
+        let replies = editor.execute(Command::SearchPreview {
+            pattern: pattern.to_string(),
+            direction: Direction::Forward,
+        });
+        let head = editor.document().selection().start();
+        match replies.iter().find_map(|reply| match reply {
+            ServerMessage::Search(status) => Some(status.clone()),
+            _ => None,
+        }) {
+            Some(Some(status)) => (head, status.current, status.total),
+            _ => (head, None, 0),
+        }
+    };
+
+    // Each preview searches from where the cursor started
+    assert_eq!(preview(&mut editor, "t"), (4, Some(0), 2));
+    assert_eq!(preview(&mut editor, "tw"), (4, Some(0), 2));
+    assert_eq!(preview(&mut editor, "twx"), (0, None, 0));
+    assert_eq!(preview(&mut editor, "One"), (0, None, 0));
+    editor.execute(Command::CancelSearch);
+    assert_eq!(editor.document().selection().start(), 0);
+
+    assert_eq!(preview(&mut editor, "one"), (12, Some(1), 2));
+    editor.execute(Command::Search {
+        pattern: "one".to_string(),
+        direction: Direction::Forward,
+    });
+    assert_eq!(editor.document().selection().start(), 12);
+    let replies = editor.execute(Command::SearchNext(Direction::Forward));
+    assert!(replies
+        .iter()
+        .any(|reply| matches!(reply, ServerMessage::Status(status) if status == "search wrapped")));
+    assert_eq!(editor.document().selection().start(), 0);
+}
+
+#[test]
+fn grep_results() {
+    let dir = std::env::temp_dir().join(format!("kavi-grep-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join(".gitignore"), "target\n").unwrap();
+    std::fs::write(dir.join("src/a.rs"), "fn main() {\n    let total = 1;\n}\n").unwrap();
+    std::fs::write(dir.join("src/b.rs"), "// total\n").unwrap();
+    std::fs::create_dir_all(dir.join("target")).unwrap();
+    std::fs::write(dir.join("target/c.rs"), "total\n").unwrap();
+
+    let mut editor = Editor::new();
+    editor.root = dir.clone();
+    let events = editor.grep_events();
+    editor.execute(Command::Grep("tot[a]l".to_string()));
+    assert_eq!(editor.document().name(), "[grep tot[a]l]");
+    let status = loop {
+        let event = events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("grep didn't finish");
+        if let [ServerMessage::Status(status)] = &editor.handle_grep(event)[..] {
+            break status.clone();
+        }
+    };
+    assert_eq!(status, "2 results, 2 files searched");
+    let separator = std::path::MAIN_SEPARATOR;
+    assert_eq!(
+        editor.document().text(),
+        &*format!(
+            "src{0}a.rs:2:9:     let total = 1;\nsrc{0}b.rs:1:4: // total\n",
+            separator
+        )
+    );
+    assert!(!editor.document().is_modified());
+
+    // Editing the results would throw off which line is which hit
+    let replies = editor.execute(Command::Insert('x'));
+    assert!(matches!(&replies[..], [ServerMessage::Error(_)]));
+    assert!(editor.document().text().to_string().starts_with("src"));
+    assert!(editor.buffers().iter().any(|buffer| buffer.results));
+
+    // The first result, from its line in the results buffer
+    editor.execute(Command::Move(crate::Motion::DocumentStart));
+    editor.execute(Command::OpenResult);
+    assert_eq!(editor.document().name(), "a.rs");
+    assert_eq!(editor.document().selection().head, 20);
+    assert_eq!(editor.buffers().len(), 2);
+
+    match &editor.execute(Command::ListFiles)[..] {
+        [ServerMessage::Files { files, .. }] => assert_eq!(
+            files,
+            &[
+                format!("src{}a.rs", separator),
+                format!("src{}b.rs", separator)
+            ]
+        ),
+        replies => panic!("expected files, got {:?}", replies),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,145 @@
+// Line diffs, for showing how a buffer differs from the file on disk.
+
+const CONTEXT: usize = 3;
+/// Above this many cells the lcs table isn't worth it and the differing middle is shown as
+/// removed then added wholesale.
+const MAX_TABLE: usize = 1 << 22;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// Diff of `old` against `new` in unified format.
+pub fn unified(old: &str, new: &str) -> String {
+    let a: Vec<_> = old.lines().collect();
+    let b: Vec<_> = new.lines().collect();
+
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut ops = vec![Op::Keep; prefix];
+    ops.extend(diff_middle(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+    ));
+    ops.extend(std::iter::repeat_n(Op::Keep, suffix));
+
+    // Walk the ops, printing each hunk of changes with some surrounding context
+    let mut out = String::new();
+    let changed: Vec<_> = ops.iter().map(|&op| op != Op::Keep).collect();
+    let mut i = 0;
+    let (mut line_a, mut line_b) = (0, 0);
+    while i < ops.len() {
+        let next = match changed[i..].iter().position(|&c| c) {
+            Some(offset) => i + offset,
+            None => break,
+        };
+        let start = next.saturating_sub(CONTEXT).max(i);
+        for op in &ops[i..start] {
+            match op {
+                Op::Keep => (line_a, line_b) = (line_a + 1, line_b + 1),
+                Op::Remove => line_a += 1,
+                Op::Add => line_b += 1,
+            }
+        }
+
+        // Extend the hunk until there's a run of more than 2 * CONTEXT unchanged lines
+        let mut end = next;
+        let mut unchanged = 0;
+        while end < ops.len() && unchanged <= 2 * CONTEXT {
+            unchanged = if changed[end] { 0 } else { unchanged + 1 };
+            end += 1;
+        }
+        let end = end - unchanged.saturating_sub(CONTEXT);
+
+        let hunk = &ops[start..end];
+        let len_a = hunk.iter().filter(|&&op| op != Op::Add).count();
+        let len_b = hunk.iter().filter(|&&op| op != Op::Remove).count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            line_a + 1,
+            len_a,
+            line_b + 1,
+            len_b
+        ));
+        for op in hunk {
+            match op {
+                Op::Keep => {
+                    out.push_str(&format!(" {}\n", a[line_a]));
+                    (line_a, line_b) = (line_a + 1, line_b + 1);
+                }
+                Op::Remove => {
+                    out.push_str(&format!("-{}\n", a[line_a]));
+                    line_a += 1;
+                }
+                Op::Add => {
+                    out.push_str(&format!("+{}\n", b[line_b]));
+                    line_b += 1;
+                }
+            }
+        }
+
+        i = end;
+    }
+
+    out
+}
+
+fn diff_middle(a: &[&str], b: &[&str]) -> Vec<Op> {
+    if a.len() * b.len() > MAX_TABLE {
+        let mut ops = vec![Op::Remove; a.len()];
+        ops.extend(std::iter::repeat_n(Op::Add, b.len()));
+        return ops;
+    }
+
+    // lengths[i][j]: longest common subsequence of a[i..] and b[j..]
+    let width = b.len() + 1;
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if a[i] == b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(Op::Keep);
+            i += 1;
+            j += 1;
+        } else if i < a.len()
+            && (j == b.len() || lengths[(i + 1) * width + j] >= lengths[i * width + j + 1])
+        {
+            ops.push(Op::Remove);
+            i += 1;
+        } else {
+            ops.push(Op::Add);
+            j += 1;
+        }
+    }
+    ops
+}
+
+#[test]
+fn unified_diff() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+    let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+    assert_eq!(
+        unified(old, new),
+        "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -10,3 +10,4 @@\n j\n k\n l\n+m\n"
+    );
+    assert_eq!(unified(old, old), "");
+}
@@ -0,0 +1,1000 @@
+use super::diff;
+use super::encoding::Encoding;
+use super::file::{self, Format};
+use super::history::{Edit, EditKind, History, Travel};
+use super::motion;
+use super::object;
+use super::search::Matcher;
+use crate::{Direction, Motion, Scope, Selection, Selections, Step};
+use ropey::Rope;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A change to the text as seen by clients, in the coordinates of the text before it was applied.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// What the file looked like when it was last loaded or saved, to notice others changing it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct DiskState {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl DiskState {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Document {
+    text: Rope,
+    path: Option<PathBuf>,
+    format: Format,
+    /// Revision that matches the file on disk.
+    saved_revision: usize,
+    disk: Option<DiskState>,
+    /// The file changed on disk while there were unsaved changes, and the user hasn't said
+    /// which version to keep yet.
+    conflict: bool,
+    /// What to call the document if it has no file.
+    title: Option<String>,
+    /// Filled in by the editor rather than typed, so edits are ignored.
+    read_only: bool,
+    selections: Selections,
+    /// Columns that vertical movement aims for, one per selection, kept while moving through
+    /// shorter lines. Empty when the selections last changed some other way.
+    desired_columns: Vec<usize>,
+    history: History,
+    changes: Vec<Change>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A document without a file, called `title` rather than `[scratch]`.
+    pub fn titled(title: impl Into<String>) -> Self {
+        Self {
+            title: Some(title.into()),
+            ..Self::new()
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let (text, format) = file::load(path, None)?;
+        Ok(Self {
+            text,
+            path: Some(path.to_owned()),
+            format,
+            disk: DiskState::read(path),
+            ..Self::new()
+        })
+    }
+
+    /// Rereads the file, decoding it as `encoding` or the current encoding. Can be undone.
+    pub fn reload(&mut self, encoding: Option<Encoding>) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => anyhow::bail!("no file name"),
+        };
+        let (text, format) = file::load(path, Some(encoding.unwrap_or(self.format.encoding)))?;
+        let disk = DiskState::read(path);
+
+        let selections = self.selections.clone();
+        self.history.commit();
+        self.replace(0, self.text.len_chars(), &text.to_string(), EditKind::Other);
+        self.history.commit();
+        let len = self.text.len_chars();
+        self.set_selections(selections.map(|selection| selection.map(|idx| idx.min(len))));
+        self.format = format;
+        self.saved_revision = self.history.current();
+        self.disk = disk;
+        self.conflict = false;
+        Ok(())
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => anyhow::bail!("no file name"),
+        };
+        if self.changed_on_disk() {
+            self.conflict = true;
+            anyhow::bail!(
+                "{} changed on disk since it was read, reload or keep this version first",
+                path.display()
+            );
+        }
+        self.write(&path)
+    }
+
+    pub fn save_as(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.write(path)?;
+        self.path = Some(path.to_owned());
+        Ok(())
+    }
+
+    fn write(&mut self, path: &Path) -> anyhow::Result<()> {
+        file::save(path, &self.text, self.format)?;
+        self.saved_revision = self.history.current();
+        self.disk = DiskState::read(path);
+        self.conflict = false;
+        Ok(())
+    }
+
+    /// Whether the file was modified or replaced by something else since it was last read or
+    /// written. A file that has disappeared doesn't count, saving just recreates it.
+    pub fn changed_on_disk(&self) -> bool {
+        match (&self.path, self.disk) {
+            (Some(path), Some(disk)) => !matches!(DiskState::read(path), Some(now) if now == disk),
+            (Some(path), None) => DiskState::read(path).is_some(),
+            (None, _) => false,
+        }
+    }
+
+    /// Whether the user has been asked about a change on disk and not answered yet.
+    pub fn has_conflict(&self) -> bool {
+        self.conflict
+    }
+
+    pub fn mark_conflict(&mut self) {
+        self.conflict = true;
+    }
+
+    /// Resolves a conflict by keeping the buffer, overwriting the file on the next save.
+    pub fn keep_buffer(&mut self) {
+        self.disk = self.path.as_deref().and_then(DiskState::read);
+        self.conflict = false;
+    }
+
+    /// Difference between the file on disk and the buffer.
+    pub fn diff_with_disk(&self) -> anyhow::Result<String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => anyhow::bail!("no file name"),
+        };
+        let (disk, _) = file::load(path, Some(self.format.encoding))?;
+        Ok(diff::unified(&disk.to_string(), &self.text.to_string()))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// File name to show in the buffer list.
+    pub fn name(&self) -> String {
+        match &self.path {
+            Some(path) => match path.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => path.display().to_string(),
+            },
+            None => self.title.as_deref().unwrap_or("[scratch]").to_string(),
+        }
+    }
+
+    /// Whether the text differs from what was last loaded or saved.
+    pub fn is_modified(&self) -> bool {
+        self.history.current() != self.saved_revision
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The primary selection.
+    pub fn selection(&self) -> Selection {
+        self.selections.primary()
+    }
+
+    pub fn selections(&self) -> &Selections {
+        &self.selections
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Ends the current group of inserts in the undo history.
+    pub fn commit(&mut self) {
+        self.history.commit();
+    }
+
+    /// Changes applied since the last call, in order.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let text = c.to_string();
+        self.replace_each(EditKind::Insert, |_, selection| {
+            Some((selection.start()..selection.end(), text.clone()))
+        });
+    }
+
+    /// Removes the selection, or the character before the cursor if nothing is selected.
+    pub fn delete(&mut self) {
+        self.replace_each(EditKind::Other, |_, selection| {
+            match (selection.is_empty(), selection.head) {
+                (false, _) => Some((selection.start()..selection.end(), String::new())),
+                (true, 0) => None,
+                (true, head) => Some((head - 1..head, String::new())),
+            }
+        });
+    }
+
+    /// Removes the selection, or the character under the cursor if nothing is selected.
+    pub fn delete_selection(&mut self) {
+        self.replace_each(EditKind::Other, |text, selection| {
+            let end = match selection.is_empty() {
+                true => motion::next_grapheme(text, selection.head),
+                false => selection.end(),
+            };
+            (selection.start() < end).then(|| (selection.start()..end, String::new()))
+        });
+    }
+
+    /// Removes the selection, or back to the start of the word before the cursor.
+    pub fn delete_word_backward(&mut self) {
+        self.replace_each(EditKind::Other, |text, selection| {
+            let start = match selection.is_empty() {
+                true => motion::prev_word(text, selection.head),
+                false => selection.start(),
+            };
+            (start < selection.end()).then(|| (start..selection.end(), String::new()))
+        });
+    }
+
+    /// Inserts a line break after the end or before the start of each cursor's line, leaving the
+    /// cursor on the new empty line.
+    pub fn open_line(&mut self, direction: Direction) {
+        self.replace_each(EditKind::Other, |text, selection| {
+            let line = text.char_to_line(selection.head);
+            let at = match direction {
+                Direction::Forward => motion::line_end(text, line),
+                Direction::Backward => text.line_to_char(line),
+            };
+            Some((at..at, "\n".to_string()))
+        });
+        if let Direction::Backward = direction {
+            // Onto the new line rather than after it
+            let selections = self
+                .selections
+                .map(|selection| Selection::point(selection.head - 1));
+            self.set_selections(selections);
+        }
+    }
+
+    /// Replaces every selection with a cursor `column` chars into `line`, or as close as the text
+    /// goes.
+    pub fn goto(&mut self, line: usize, column: usize) {
+        let line = line.min(self.text.len_lines() - 1);
+        let idx = self.text.line_to_char(line) + column;
+        let idx = idx.min(motion::line_end(&self.text, line));
+        self.set_selections(Selections::single(Selection::point(idx)));
+    }
+
+    /// Replaces every selection with one selecting `range`.
+    pub fn select(&mut self, range: Range<usize>) {
+        self.set_selections(Selections::single(Selection {
+            anchor: range.start,
+            head: range.end,
+        }));
+    }
+
+    /// Moves every cursor, collapsing the selections. Pages are `page_height` lines.
+    pub fn move_cursor(&mut self, motion: Motion, page_height: usize) {
+        let mut columns = Vec::new();
+        let ranges = self
+            .selections
+            .ranges()
+            .iter()
+            .enumerate()
+            .map(|(idx, selection)| {
+                let mut column = self.desired_columns.get(idx).copied();
+                let head = self.target(selection.head, motion, page_height, &mut column);
+                columns.extend(column);
+                Selection::point(head)
+            });
+        let selections = Selections::new(ranges.collect(), self.selections.primary_index());
+
+        let vertical = matches!(
+            motion,
+            Motion::Up | Motion::Down | Motion::PageUp | Motion::PageDown
+        );
+        let keep_columns = vertical && columns.len() == selections.len();
+        self.set_selections(selections);
+        if keep_columns {
+            self.desired_columns = columns;
+        }
+    }
+
+    /// Selects from each cursor to where moving it `count` times would take it.
+    pub fn select_to(&mut self, motion: Motion, count: usize, page_height: usize) {
+        let selections = self.selections.map(|selection| {
+            let mut column = None;
+            let mut head = selection.head;
+            for _ in 0..count {
+                head = self.target(head, motion, page_height, &mut column);
+            }
+            Selection {
+                anchor: selection.head,
+                head,
+            }
+        });
+        self.set_selections(selections);
+    }
+
+    /// Where `motion` takes the cursor from `head`. Vertical motions keep to `column`, which is
+    /// set to the column of `head` if there isn't one yet.
+    fn target(
+        &self,
+        head: usize,
+        motion: Motion,
+        page_height: usize,
+        column: &mut Option<usize>,
+    ) -> usize {
+        let text = &self.text;
+        let line = text.char_to_line(head);
+        let last_line = text.len_lines() - 1;
+
+        let mut vertical = |lines: isize| {
+            let target = (line as isize + lines).clamp(0, last_line as isize) as usize;
+            let column = *column.get_or_insert_with(|| motion::column(text, head));
+            motion::at_column(text, target, column)
+        };
+
+        let page = page_height as isize;
+        match motion {
+            Motion::Left => motion::prev_grapheme(text, head),
+            Motion::Right => motion::next_grapheme(text, head),
+            Motion::Up => vertical(-1),
+            Motion::Down => vertical(1),
+            Motion::PageUp => vertical(-page),
+            Motion::PageDown => vertical(page),
+            Motion::WordForward => motion::next_word(text, head),
+            Motion::WordBackward => motion::prev_word(text, head),
+            Motion::LineStart => text.line_to_char(line),
+            Motion::LineEnd => motion::line_end(text, line),
+            Motion::ParagraphForward => motion::next_paragraph(text, head),
+            Motion::ParagraphBackward => motion::prev_paragraph(text, head),
+            Motion::DocumentStart => 0,
+            Motion::DocumentEnd => text.len_chars(),
+        }
+    }
+
+    /// Selects the text object `scope` at each cursor. Brackets and quotes grow the selection
+    /// outwards, and leave it alone if there's nothing around it.
+    pub fn select_object(&mut self, scope: Scope) {
+        let text = &self.text;
+        let selections = self.selections.map(|selection| {
+            let head = selection.head;
+            let range = selection.start()..selection.end();
+            let object = match scope {
+                Scope::Character => Some(head..motion::next_grapheme(text, head)),
+                Scope::Line => {
+                    let line = text.char_to_line(head);
+                    Some(text.line_to_char(line)..text.line_to_char(line + 1))
+                }
+                Scope::Word => Some(object::word(text, head)),
+                Scope::Paragraph => Some(object::paragraph(text, head)),
+                Scope::Brackets => object::brackets(text, range),
+                Scope::Quotes => object::quotes(text, range),
+                Scope::Indent => Some(object::indent(text, head)),
+                Scope::Buffer => Some(0..text.len_chars()),
+            };
+            match object {
+                Some(object) => Selection {
+                    anchor: object.start,
+                    head: object.end,
+                },
+                None => selection,
+            }
+        });
+        self.set_selections(selections);
+    }
+
+    /// Extends each selection of whole lines by the next line in `direction`. Anything else is
+    /// replaced by a selection of the cursor's line.
+    pub fn grow_lines(&mut self, direction: Direction) {
+        let text = &self.text;
+        let selections = self.selections.map(|selection| {
+            let (start, end) = (selection.start(), selection.end());
+            let start_line = text.char_to_line(start);
+            let end_line = text.char_to_line(end);
+            let whole_lines = start < end
+                && text.line_to_char(start_line) == start
+                && (text.line_to_char(end_line) == end || end == text.len_chars());
+            match (whole_lines, direction) {
+                (false, _) => {
+                    let line = text.char_to_line(selection.head);
+                    Selection {
+                        anchor: text.line_to_char(line),
+                        head: text.line_to_char(line + 1),
+                    }
+                }
+                (true, Direction::Forward) => Selection {
+                    anchor: start,
+                    head: text.line_to_char((end_line + 1).min(text.len_lines())),
+                },
+                (true, Direction::Backward) => Selection {
+                    anchor: end,
+                    head: text.line_to_char(start_line.saturating_sub(1)),
+                },
+            }
+        });
+        self.set_selections(selections);
+    }
+
+    /// Adds a cursor on the line after the last selection or before the first, at the same
+    /// column, and makes it the primary one.
+    pub fn add_cursor(&mut self, direction: Direction) {
+        let text = &self.text;
+        let ranges = self.selections.ranges();
+        let (from, line) = match direction {
+            Direction::Forward => {
+                let from = ranges[ranges.len() - 1].head;
+                (from, text.char_to_line(from) + 1)
+            }
+            Direction::Backward => {
+                let from = ranges[0].head;
+                match text.char_to_line(from) {
+                    0 => return,
+                    line => (from, line - 1),
+                }
+            }
+        };
+        if line >= text.len_lines() {
+            return;
+        }
+
+        let head = motion::at_column(text, line, motion::column(text, from));
+        let mut ranges = ranges.to_vec();
+        ranges.push(Selection::point(head));
+        let primary = ranges.len() - 1;
+        self.set_selections(Selections::new(ranges, primary));
+    }
+
+    /// Splits each selection into one per line it covers, without the line breaks.
+    pub fn split_lines(&mut self) {
+        let text = &self.text;
+        let mut ranges = Vec::new();
+        let mut primary = 0;
+        for (idx, selection) in self.selections.ranges().iter().enumerate() {
+            if idx == self.selections.primary_index() {
+                primary = ranges.len();
+            }
+            let (start, end) = (selection.start(), selection.end());
+            let (first, last) = (text.char_to_line(start), text.char_to_line(end));
+            if first == last {
+                ranges.push(*selection);
+                continue;
+            }
+            for line in first..=last {
+                let line_start = text.line_to_char(line).max(start);
+                let line_end = motion::line_end(text, line).min(end);
+                if line_start < line_end {
+                    ranges.push(Selection {
+                        anchor: line_start,
+                        head: line_end,
+                    });
+                }
+            }
+        }
+        if !ranges.is_empty() {
+            let primary = primary.min(ranges.len() - 1);
+            self.set_selections(Selections::new(ranges, primary));
+        }
+    }
+
+    /// Replaces the selections with every match of `pattern` inside them. The first match at or
+    /// after the primary selection becomes the primary one.
+    pub fn select_matches(&mut self, pattern: &str) -> anyhow::Result<()> {
+        let mut matcher = Matcher::new(pattern)?;
+        let mut ranges = Vec::new();
+        let mut primary = None;
+        for (idx, selection) in self.selections.ranges().iter().enumerate() {
+            if idx == self.selections.primary_index() {
+                primary = Some(ranges.len());
+            }
+            let found = matcher.matches(&self.text, selection.start()..selection.end());
+            ranges.extend(
+                found
+                    .filter(|found| !found.is_empty())
+                    .map(|found| Selection {
+                        anchor: found.start,
+                        head: found.end,
+                    }),
+            );
+        }
+        if ranges.is_empty() {
+            anyhow::bail!("no matches for {} in the selection", pattern);
+        }
+        let primary = primary.unwrap_or(0).min(ranges.len() - 1);
+        self.set_selections(Selections::new(ranges, primary));
+        Ok(())
+    }
+
+    /// Drops every selection but the primary one.
+    pub fn keep_primary(&mut self) {
+        self.set_selections(Selections::single(self.selections.primary()));
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let travel = self.history.undo();
+        self.travel(travel)
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let travel = self.history.redo();
+        self.travel(travel)
+    }
+
+    pub fn earlier(&mut self, step: Step) -> bool {
+        let travel = self.history.earlier(step);
+        self.travel(travel)
+    }
+
+    pub fn later(&mut self, step: Step) -> bool {
+        let travel = self.history.later(step);
+        self.travel(travel)
+    }
+
+    pub fn goto_revision(&mut self, revision: usize) -> bool {
+        let travel = self.history.goto(revision);
+        self.travel(travel)
+    }
+
+    pub fn set_selections(&mut self, selections: Selections) {
+        self.selections = selections;
+        self.desired_columns.clear();
+    }
+
+    fn travel(&mut self, travel: Option<Travel>) -> bool {
+        match travel {
+            Some(Travel { edits, selections }) => {
+                for edit in edits.iter() {
+                    self.apply(edit);
+                }
+                self.set_selections(selections);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces `start..end` with `text`, leaving a single cursor after it.
+    fn replace(&mut self, start: usize, end: usize, text: &str, kind: EditKind) {
+        if self.read_only {
+            return;
+        }
+        let before = self.selections.clone();
+        let edits = self.replace_ranges(&[(start..end, text.to_owned())]);
+        self.set_selections(Selections::single(Selection::point(
+            start + text.chars().count(),
+        )));
+        self.history
+            .record(edits, kind, before, self.selections.clone());
+    }
+
+    /// Makes an edit at each selection, all as one change to undo. `edit` gives the range to
+    /// replace and what to replace it with, or `None` to leave that selection alone; edits that
+    /// overlap an earlier one are dropped. Each edited selection becomes a cursor after its new
+    /// text, and the others move along with the text around them.
+    fn replace_each(
+        &mut self,
+        kind: EditKind,
+        mut edit: impl FnMut(&Rope, Selection) -> Option<(Range<usize>, String)>,
+    ) {
+        if self.read_only {
+            return;
+        }
+        let before = self.selections.clone();
+        let mut edited = Vec::new();
+        let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+        for (idx, &selection) in before.ranges().iter().enumerate() {
+            if let Some((range, text)) = edit(&self.text, selection) {
+                let overlaps = matches!(
+                    replacements.last(),
+                    Some((last, _)) if range.start < last.end || range == *last
+                );
+                if !overlaps {
+                    edited.push(idx);
+                    replacements.push((range, text));
+                }
+            }
+        }
+        if replacements.is_empty() {
+            return;
+        }
+
+        let edits = self.replace_ranges(&replacements);
+        let mut cursors = vec![None; before.len()];
+        let mut shift = 0isize;
+        for (&idx, (range, text)) in edited.iter().zip(&replacements) {
+            let inserted = text.chars().count();
+            cursors[idx] = Some((range.start as isize + shift) as usize + inserted);
+            shift += inserted as isize - range.len() as isize;
+        }
+        let ranges = before
+            .ranges()
+            .iter()
+            .zip(cursors)
+            .map(|(selection, cursor)| match cursor {
+                Some(cursor) => Selection::point(cursor),
+                None => selection.map(|idx| moved(&replacements, idx)),
+            });
+        self.set_selections(Selections::new(ranges.collect(), before.primary_index()));
+        self.history
+            .record(edits, kind, before, self.selections.clone());
+    }
+
+    /// What replacing every match of `pattern` with `replacement` would do, as the ranges to
+    /// replace and what goes in each. See `Matcher::expand` for what `replacement` can refer to.
+    pub fn replacements(
+        &self,
+        pattern: &str,
+        replacement: &str,
+    ) -> anyhow::Result<Vec<(Range<usize>, String)>> {
+        let mut matcher = Matcher::new(pattern)?;
+        let found: Vec<_> = matcher
+            .matches(&self.text, 0..self.text.len_chars())
+            .collect();
+        Ok(found
+            .into_iter()
+            .map(|found| {
+                let with = matcher.expand(&self.text, found.clone(), replacement);
+                (found, with)
+            })
+            .collect())
+    }
+
+    /// Makes all of `replacements`, sorted and not overlapping, as one change to undo. The
+    /// selections move along with the text around them.
+    pub fn replace_all(&mut self, replacements: Vec<(Range<usize>, String)>) {
+        if replacements.is_empty() || self.read_only {
+            return;
+        }
+        let before = self.selections.clone();
+        let edits = self.replace_ranges(&replacements);
+        self.set_selections(before.map(|selection| selection.map(|idx| moved(&replacements, idx))));
+        self.history
+            .record(edits, EditKind::Other, before, self.selections.clone());
+    }
+
+    /// Replaces each of the sorted, non-overlapping `replacements`, returning the edits made.
+    fn replace_ranges(&mut self, replacements: &[(Range<usize>, String)]) -> Vec<Edit> {
+        // Back to front, so that the positions of the edits still to be made don't move
+        let mut edits = Vec::new();
+        for (range, text) in replacements.iter().rev() {
+            let edit = Edit {
+                start: range.start,
+                removed: self.text.slice(range.clone()).to_string(),
+                inserted: text.clone(),
+            };
+            self.apply(&edit);
+            edits.push(edit);
+        }
+        edits
+    }
+
+    /// Adds `text` to the end without recording it for undo, for documents filled in by the
+    /// editor rather than typed.
+    pub fn append(&mut self, text: &str) {
+        let edit = Edit {
+            start: self.text.len_chars(),
+            removed: String::new(),
+            inserted: text.to_string(),
+        };
+        self.apply(&edit);
+    }
+
+    /// Applies `edit`, leaving the selections as they are.
+    fn apply(&mut self, edit: &Edit) {
+        let removed_len = edit.removed.chars().count();
+        let range = edit.start..edit.start + removed_len;
+        self.text.remove(range.clone());
+        self.text.insert(edit.start, &edit.inserted);
+        self.changes.push(Change {
+            range,
+            text: edit.inserted.clone(),
+        });
+    }
+}
+
+/// Where position `idx` ends up after making `replacements`. Positions inside a replaced range
+/// end up after its new text.
+fn moved(replacements: &[(Range<usize>, String)], idx: usize) -> usize {
+    let mut shift = 0isize;
+    for (range, text) in replacements {
+        if range.start >= idx {
+            break;
+        }
+        let inserted = text.chars().count();
+        if range.end > idx {
+            return (range.start as isize + shift) as usize + inserted;
+        }
+        shift += inserted as isize - range.len() as isize;
+    }
+    (idx as isize + shift) as usize
+}
+
+#[test]
+fn vertical_moves_keep_column() {
+    let mut document = Document::new();
+    for c in "long line\nab\nanother line".chars() {
+        document.insert(c);
+    }
+    document.move_cursor(Motion::DocumentStart, 10);
+    for _ in 0..6 {
+        document.move_cursor(Motion::Right, 10);
+    }
+
+    // Clamped to the short line, then back to column 6
+    document.move_cursor(Motion::Down, 10);
+    assert_eq!(document.selection().head, 12);
+    document.move_cursor(Motion::Down, 10);
+    assert_eq!(document.selection().head, 19);
+
+    // Moving sideways forgets it
+    document.move_cursor(Motion::Left, 10);
+    document.move_cursor(Motion::PageUp, 10);
+    assert_eq!(document.selection().head, 5);
+}
+
+#[test]
+fn grow_lines() {
+    let mut document = Document::new();
+    for c in "one\ntwo\nthree".chars() {
+        document.insert(c);
+    }
+    document.move_cursor(Motion::Up, 10);
+    document.grow_lines(Direction::Forward);
+    assert_eq!(document.selection(), Selection { anchor: 4, head: 8 });
+    document.grow_lines(Direction::Forward);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 4,
+            head: 13
+        }
+    );
+    document.grow_lines(Direction::Backward);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 13,
+            head: 0
+        }
+    );
+
+    document.open_line(Direction::Backward);
+    assert_eq!(document.text(), "\none\ntwo\nthree");
+    assert_eq!(document.selection(), Selection::point(0));
+}
+
+#[test]
+fn select_to() {
+    let mut document = Document::new();
+    for c in "one two three four".chars() {
+        document.insert(c);
+    }
+    document.move_cursor(Motion::DocumentStart, 10);
+    document.move_cursor(Motion::WordForward, 10);
+
+    document.select_to(Motion::WordForward, 2, 10);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 4,
+            head: 14
+        }
+    );
+    document.delete_selection();
+    assert_eq!(document.text().to_string(), "one four");
+}
+
+#[test]
+fn select_objects() {
+    let mut document = Document::new();
+    for c in "call(one, \"two\")".chars() {
+        document.insert(c);
+    }
+    document.select(12..12);
+
+    document.select_object(Scope::Quotes);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 11,
+            head: 14
+        }
+    );
+    document.select_object(Scope::Brackets);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 5,
+            head: 15
+        }
+    );
+    document.select_object(Scope::Brackets);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 4,
+            head: 16
+        }
+    );
+
+    // Nothing further out
+    document.select_object(Scope::Brackets);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 4,
+            head: 16
+        }
+    );
+    document.select_object(Scope::Buffer);
+    assert_eq!(
+        document.selection(),
+        Selection {
+            anchor: 0,
+            head: 16
+        }
+    );
+}
+
+#[test]
+fn multiple_cursors() {
+    let mut document = Document::new();
+    for c in "one\ntwo\nthree".chars() {
+        document.insert(c);
+    }
+    document.move_cursor(Motion::DocumentStart, 10);
+    document.add_cursor(Direction::Forward);
+    document.add_cursor(Direction::Forward);
+    assert_eq!(document.selections().len(), 3);
+    assert_eq!(document.selection(), Selection::point(8));
+
+    // Each insert shifts the cursors after it
+    document.insert('-');
+    document.insert('>');
+    assert_eq!(document.text().to_string(), "->one\n->two\n->three");
+    let heads: Vec<_> = document
+        .selections()
+        .ranges()
+        .iter()
+        .map(|s| s.head)
+        .collect();
+    assert_eq!(heads, [2, 8, 14]);
+
+    document.delete();
+    document.delete();
+    assert_eq!(document.text().to_string(), "one\ntwo\nthree");
+    document.delete_selection();
+    assert_eq!(document.text().to_string(), "ne\nwo\nhree");
+
+    // Each change is undone at every cursor at once
+    document.undo();
+    document.undo();
+    document.undo();
+    assert_eq!(document.text().to_string(), "->one\n->two\n->three");
+    document.undo();
+    assert_eq!(document.text().to_string(), "one\ntwo\nthree");
+    assert_eq!(document.selections().len(), 3);
+
+    // Moving onto the same place merges cursors
+    document.move_cursor(Motion::DocumentStart, 10);
+    assert_eq!(
+        *document.selections(),
+        Selections::single(Selection::point(0))
+    );
+}
+
+#[test]
+fn split_and_select_matches() {
+    let mut document = Document::new();
+    for c in "let a = 1;\nlet bb = 22;\nlet c = 3;".chars() {
+        document.insert(c);
+    }
+    document.select(4..document.text().len_chars());
+    document.split_lines();
+    let ranges: Vec<_> = document
+        .selections()
+        .ranges()
+        .iter()
+        .map(|s| s.start()..s.end())
+        .collect();
+    assert_eq!(ranges, [4..10, 11..23, 24..34]);
+
+    document.select_matches(r"\d+").unwrap();
+    let ranges: Vec<_> = document
+        .selections()
+        .ranges()
+        .iter()
+        .map(|s| s.start()..s.end())
+        .collect();
+    assert_eq!(ranges, [8..9, 20..22, 32..33]);
+
+    document.insert('0');
+    assert_eq!(
+        document.text().to_string(),
+        "let a = 0;\nlet bb = 0;\nlet c = 0;"
+    );
+    assert!(document.select_matches("x").is_err());
+
+    document.keep_primary();
+    assert_eq!(document.selections().len(), 1);
+
+    // Case is ignored unless the pattern has upper case in it, as when searching
+    let mut document = Document::new();
+    for c in "Foo foo".chars() {
+        document.insert(c);
+    }
+    document.select(0..7);
+    document.select_matches("foo").unwrap();
+    assert_eq!(document.selections().len(), 2);
+    document.select(0..7);
+    document.select_matches("Foo").unwrap();
+    assert_eq!(document.selections().len(), 1);
+}
+
+#[test]
+fn replace_all_undoes_at_once() {
+    let mut document = Document::new();
+    for c in "fn a(x: u8) {}\nfn b(y: u16) {}\n".chars() {
+        document.insert(c);
+    }
+    document.select(18..19);
+
+    let replacements = document
+        .replacements(r"(\w): u(\d+)", "$1: Option<i$2>")
+        .unwrap();
+    assert_eq!(replacements.len(), 2);
+    document.replace_all(replacements);
+    assert_eq!(
+        document.text(),
+        "fn a(x: Option<i8>) {}\nfn b(y: Option<i16>) {}\n"
+    );
+    // Moved along by the first replacement
+    let selection = document.selection();
+    assert_eq!((selection.start(), selection.end()), (26, 27));
+
+    assert!(document.undo());
+    assert_eq!(document.text(), "fn a(x: u8) {}\nfn b(y: u16) {}\n");
+    assert_eq!(document.selection().start(), 18);
+
+    // Undoing a replacement of everything, as reloading makes, brings the selection back too
+    document.replace(0, document.text().len_chars(), "new", EditKind::Other);
+    assert_eq!(document.selection(), Selection::point(3));
+    assert!(document.undo());
+    assert_eq!(document.selection().start(), 18);
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+// Bytes that can't be decoded are kept in the text as chars from the end of the last private use
+// plane, U+10FF00 + byte, and turned back into the same bytes on save. Text that genuinely
+// contains those chars is escaped the same way, so every file round-trips exactly.
+const ESCAPE_BASE: u32 = 0x10ff00;
+
+fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE + byte as u32).unwrap()
+}
+
+fn unescape(c: char) -> Option<u8> {
+    (c as u32).checked_sub(ESCAPE_BASE).map(|byte| byte as u8)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin-1",
+        })
+    }
+}
+
+impl Encoding {
+    /// The byte order mark for this encoding, empty if it doesn't have one.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 => &[0xef, 0xbb, 0xbf],
+            Self::Utf16Le => &[0xff, 0xfe],
+            Self::Utf16Be => &[0xfe, 0xff],
+            Self::Latin1 => &[],
+        }
+    }
+
+    /// Sniffs a byte order mark, falling back to guessing from the contents.
+    pub fn detect(bytes: &[u8]) -> Self {
+        for encoding in [Self::Utf8, Self::Utf16Le, Self::Utf16Be] {
+            if bytes.starts_with(encoding.bom()) {
+                return encoding;
+            }
+        }
+
+        // Mostly-ascii utf-16 has a zero in every other byte
+        let sample = &bytes[..bytes.len().min(4096) & !1];
+        let pairs = sample.len() / 2;
+        if pairs > 0 {
+            let even_zeros = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+            let odd_zeros = sample
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .filter(|&&b| b == 0)
+                .count();
+            if odd_zeros * 2 > pairs && even_zeros * 8 < pairs {
+                return Self::Utf16Le;
+            }
+            if even_zeros * 2 > pairs && odd_zeros * 8 < pairs {
+                return Self::Utf16Be;
+            }
+        }
+
+        // Legacy 8-bit text rarely forms valid multi-byte sequences, while utf-8 with a few stray
+        // bytes is mostly valid ones
+        let mut valid = 0;
+        let mut invalid = 0;
+        for chunk in bytes.utf8_chunks() {
+            valid += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+            invalid += !chunk.invalid().is_empty() as usize;
+        }
+
+        if invalid == 0 || valid > invalid {
+            Self::Utf8
+        } else {
+            Self::Latin1
+        }
+    }
+
+    /// Decodes `bytes`, and whether they started with a byte order mark.
+    pub fn decode(self, bytes: &[u8]) -> (String, bool) {
+        let (bytes, bom) = match bytes.strip_prefix(self.bom()) {
+            Some(rest) if !self.bom().is_empty() => (rest, true),
+            _ => (bytes, false),
+        };
+        let mut text = String::with_capacity(bytes.len());
+        match self {
+            Self::Utf8 => decode_utf8(bytes, &mut text),
+            Self::Utf16Le => decode_utf16(bytes, u16::from_le_bytes, &mut text),
+            Self::Utf16Be => decode_utf16(bytes, u16::from_be_bytes, &mut text),
+            Self::Latin1 => text.extend(bytes.iter().map(|&b| b as char)),
+        }
+        (text, bom)
+    }
+
+    pub fn encode(self, text: &str, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        for c in text.chars() {
+            if let Some(byte) = unescape(c) {
+                out.push(byte);
+                continue;
+            }
+
+            match self {
+                Self::Utf8 => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                Self::Utf16Le => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.extend_from_slice(&unit.to_le_bytes());
+                    }
+                }
+                Self::Utf16Be => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.extend_from_slice(&unit.to_be_bytes());
+                    }
+                }
+                Self::Latin1 => match u8::try_from(c as u32) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => anyhow::bail!("{:?} can't be encoded as {}", c, self),
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_utf8(bytes: &[u8], text: &mut String) {
+    fn push_valid(s: &str, text: &mut String) {
+        for c in s.chars() {
+            match unescape(c) {
+                Some(_) => text.extend(c.encode_utf8(&mut [0; 4]).bytes().map(escape)),
+                None => text.push(c),
+            }
+        }
+    }
+
+    for chunk in bytes.utf8_chunks() {
+        push_valid(chunk.valid(), text);
+        text.extend(chunk.invalid().iter().copied().map(escape));
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16, text: &mut String) {
+    let pairs = bytes.chunks_exact(2);
+    let odd = pairs.remainder();
+
+    let mut offset = 0;
+    let units = pairs.map(|pair| from_bytes([pair[0], pair[1]]));
+    for result in char::decode_utf16(units) {
+        let len = result.as_ref().map_or(2, |c| c.len_utf16() * 2);
+        match result {
+            Ok(c) if unescape(c).is_none() => text.push(c),
+            _ => text.extend(bytes[offset..offset + len].iter().copied().map(escape)),
+        }
+        offset += len;
+    }
+
+    text.extend(odd.iter().copied().map(escape));
+}
+
+#[cfg(test)]
+fn round_trip(bytes: &[u8]) -> Encoding {
+    let encoding = Encoding::detect(bytes);
+    let (text, bom) = encoding.decode(bytes);
+    let mut out = Vec::new();
+    if bom {
+        out.extend_from_slice(encoding.bom());
+    }
+    encoding.encode(&text, &mut out).unwrap();
+    assert_eq!(out, bytes, "{} didn't round trip", encoding);
+    encoding
+}
+
+#[test]
+fn detect_and_round_trip() {
+    assert_eq!(round_trip("plain ascii\n".as_bytes()), Encoding::Utf8);
+    assert_eq!(round_trip("ünïcödé\n".as_bytes()), Encoding::Utf8);
+    assert_eq!(round_trip(b"\xef\xbb\xbfbom\n"), Encoding::Utf8);
+    assert_eq!(round_trip(b"\xff\xfeh\0i\0\n\0"), Encoding::Utf16Le);
+    assert_eq!(round_trip(b"\xfe\xff\0h\0i\0\n"), Encoding::Utf16Be);
+    assert_eq!(round_trip(b"h\0e\0l\0l\0o\0\n\0"), Encoding::Utf16Le);
+    assert_eq!(round_trip(b"caf\xe9 cr\xe8me\n"), Encoding::Latin1);
+}
+
+#[test]
+fn invalid_bytes_are_preserved() {
+    // Stray byte in otherwise utf-8 text
+    let mut bytes = "héllo \u{1F600} wörld!".as_bytes().to_vec();
+    bytes.insert(3, 0xc0);
+    bytes.push(0xff);
+    assert_eq!(round_trip(&bytes), Encoding::Utf8);
+
+    // Unpaired surrogate and odd trailing byte
+    assert_eq!(round_trip(b"\xff\xfea\0\x00\xd8b\0\x01"), Encoding::Utf16Le);
+
+    // Text that happens to contain the escape range
+    assert_eq!(round_trip("x\u{10ff41}y".as_bytes()), Encoding::Utf8);
+}
+
+#[test]
+fn latin1_rejects_unrepresentable_chars() {
+    let mut out = Vec::new();
+    assert!(Encoding::Latin1.encode("€", &mut out).is_err());
+}
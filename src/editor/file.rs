@@ -0,0 +1,200 @@
+use super::encoding::Encoding;
+use anyhow::Context as _;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// How a file was laid out on disk, so it can be written back the same way. Documents always
+/// hold `\n` line endings internally.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub encoding: Encoding,
+    /// Whether the file starts with a byte order mark.
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    pub trailing_newline: bool,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::default(),
+            bom: false,
+            line_ending: if cfg!(windows) {
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
+            },
+            trailing_newline: true,
+        }
+    }
+}
+
+/// Reads a file, detecting its encoding unless one is given.
+pub fn load(path: &Path, encoding: Option<Encoding>) -> anyhow::Result<(Rope, Format)> {
+    let bytes = fs::read(path)?;
+    let encoding = encoding.unwrap_or_else(|| Encoding::detect(&bytes));
+    let (text, bom) = encoding.decode(&bytes);
+    let (text, format) = decode_lines(&text);
+    Ok((
+        text,
+        Format {
+            encoding,
+            bom,
+            ..format
+        },
+    ))
+}
+
+/// Detects the line ending style of `text` and normalises it to `\n`.
+pub fn decode_lines(text: &str) -> (Rope, Format) {
+    let lf = text.matches('\n').count();
+    let crlf = text.matches("\r\n").count();
+    let line_ending = match lf {
+        0 => Format::default().line_ending,
+        _ if crlf * 2 > lf => LineEnding::CrLf,
+        _ => LineEnding::Lf,
+    };
+    let format = Format {
+        line_ending,
+        trailing_newline: text.ends_with('\n'),
+        ..Format::default()
+    };
+
+    let text = match line_ending {
+        LineEnding::CrLf => Rope::from_str(&text.replace("\r\n", "\n")),
+        LineEnding::Lf => Rope::from_str(text),
+    };
+    (text, format)
+}
+
+/// Writes `text` to `path` atomically: a temporary file next to it is written and synced, then
+/// renamed over the original, so a failure part way through never leaves a truncated file.
+pub fn save(path: &Path, text: &Rope, format: Format) -> anyhow::Result<()> {
+    // Write through symlinks rather than replacing them
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let temp = temp_path(&path)?;
+
+    let result = write(&temp, text, format).and_then(|_| {
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, &path)?;
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn temp_path(path: &Path) -> anyhow::Result<PathBuf> {
+    let name = path.file_name().context("path has no file name")?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".kavi-{}.tmp", std::process::id()));
+    Ok(path.with_file_name(temp_name))
+}
+
+fn write(path: &Path, text: &Rope, format: Format) -> anyhow::Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    let mut buf = Vec::new();
+    if format.bom {
+        buf.extend_from_slice(format.encoding.bom());
+    }
+
+    let len = text.len_chars();
+    let missing_newline = format.trailing_newline && len > 0 && text.char(len - 1) != '\n';
+    let chunks = text.chunks().chain(missing_newline.then_some("\n"));
+
+    for chunk in chunks {
+        match format.line_ending {
+            LineEnding::Lf => format.encoding.encode(chunk, &mut buf)?,
+            LineEnding::CrLf => format
+                .encoding
+                .encode(&chunk.replace('\n', "\r\n"), &mut buf)?,
+        }
+        writer.write_all(&buf)?;
+        buf.clear();
+    }
+
+    writer.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+#[test]
+fn crlf_round_trip() {
+    let path = std::env::temp_dir().join(format!("kavi-crlf-{}.txt", std::process::id()));
+    fs::write(&path, "one\r\ntwo\r\n").unwrap();
+
+    let (text, format) = load(&path, None).unwrap();
+    assert_eq!(text, "one\ntwo\n");
+    assert_eq!(format.line_ending, LineEnding::CrLf);
+    assert!(format.trailing_newline);
+
+    save(&path, &text, format).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"one\r\ntwo\r\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn missing_trailing_newline_is_preserved() {
+    let (text, format) = decode_lines("no newline");
+    assert!(!format.trailing_newline);
+
+    let path = std::env::temp_dir().join(format!("kavi-eol-{}.txt", std::process::id()));
+    save(&path, &text, format).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"no newline");
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions_are_preserved() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("kavi-perms-{}.sh", std::process::id()));
+    fs::write(&path, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+
+    save(
+        &path,
+        &Rope::from_str("#!/bin/sh\necho\n"),
+        Format::default(),
+    )
+    .unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o751);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn utf16_crlf_round_trip() {
+    let path = std::env::temp_dir().join(format!("kavi-utf16-{}.txt", std::process::id()));
+    let bytes = b"\xff\xfea\0\r\0\n\0b\0\r\0\n\0";
+    fs::write(&path, bytes).unwrap();
+
+    let (text, format) = load(&path, None).unwrap();
+    assert_eq!(text, "a\nb\n");
+    assert_eq!(format.encoding, Encoding::Utf16Le);
+    assert_eq!(format.line_ending, LineEnding::CrLf);
+
+    save(&path, &text, format).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+    fs::remove_file(&path).unwrap();
+}
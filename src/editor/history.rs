@@ -0,0 +1,374 @@
+use crate::{Selections, Step};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// A single replacement of `removed` with `inserted` at char index `start`.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Self {
+        Self {
+            start: self.start,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EditKind {
+    /// Typed text; consecutive inserts are grouped into one transaction.
+    Insert,
+    Other,
+}
+
+/// Edits that are undone and redone as a unit, along with where the cursors were on either side.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub edits: Vec<Edit>,
+    pub before: Selections,
+    pub after: Selections,
+    kind: EditKind,
+    open: bool,
+}
+
+impl Transaction {
+    pub fn describe(&self) -> String {
+        fn shorten(s: String) -> String {
+            match s.char_indices().nth(24) {
+                Some((idx, _)) => format!("{:?}...", &s[..idx]),
+                None => format!("{:?}", s),
+            }
+        }
+
+        let inserted: String = self.edits.iter().map(|e| e.inserted.as_str()).collect();
+        let removed: String = self.edits.iter().map(|e| e.removed.as_str()).collect();
+        match (removed.is_empty(), inserted.is_empty()) {
+            (true, _) => format!("insert {}", shorten(inserted)),
+            (false, true) => format!("delete {}", shorten(removed)),
+            (false, false) => format!("replace {} with {}", shorten(removed), shorten(inserted)),
+        }
+    }
+}
+
+struct Revision {
+    parent: usize,
+    /// Child that redo moves to: the most recently created or visited one.
+    last_child: Option<usize>,
+    /// Edits leading from the parent to this revision.
+    transaction: Transaction,
+    time: Instant,
+}
+
+/// Undo tree. Revisions are numbered in the order they were made; undoing and then editing starts
+/// a new branch instead of throwing the undone revisions away.
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let root = Revision {
+            parent: 0,
+            last_child: None,
+            transaction: Transaction {
+                edits: Vec::new(),
+                before: Selections::default(),
+                after: Selections::default(),
+                kind: EditKind::Other,
+                open: false,
+            },
+            time: Instant::now(),
+        };
+
+        Self {
+            revisions: vec![root],
+            current: 0,
+        }
+    }
+}
+
+/// A step through history: edits to apply in order, then where to put the cursors.
+pub struct Travel {
+    pub edits: Vec<Edit>,
+    pub selections: Selections,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionInfo {
+    pub parent: usize,
+    pub description: String,
+    /// Time since the revision was made.
+    pub age: Duration,
+}
+
+impl History {
+    /// Records `edits`, made in order, as a new revision, or as part of the current one if
+    /// they're typing that carries on from where the last typing left the cursors.
+    pub fn record(
+        &mut self,
+        edits: Vec<Edit>,
+        kind: EditKind,
+        before: Selections,
+        after: Selections,
+    ) {
+        let current = &mut self.revisions[self.current];
+        let continues = current.transaction.open
+            && current.last_child.is_none()
+            && kind == EditKind::Insert
+            && current.transaction.kind == EditKind::Insert
+            && current.transaction.after == before;
+        if continues {
+            current.transaction.edits.extend(edits);
+            current.transaction.after = after;
+            current.time = Instant::now();
+            return;
+        }
+        current.transaction.open = false;
+
+        let id = self.revisions.len();
+        self.revisions[self.current].last_child = Some(id);
+        self.revisions.push(Revision {
+            parent: self.current,
+            last_child: None,
+            transaction: Transaction {
+                edits,
+                before,
+                after,
+                kind,
+                open: kind == EditKind::Insert,
+            },
+            time: Instant::now(),
+        });
+        self.current = id;
+    }
+
+    /// Ends the current group of inserts, so the next edit starts a new revision.
+    pub fn commit(&mut self) {
+        self.revisions[self.current].transaction.open = false;
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Moves to the parent revision.
+    pub fn undo(&mut self) -> Option<Travel> {
+        match self.current {
+            0 => None,
+            current => Some(self.travel(self.revisions[current].parent)),
+        }
+    }
+
+    /// Moves to the most recently visited child revision.
+    pub fn redo(&mut self) -> Option<Travel> {
+        let child = self.revisions[self.current].last_child?;
+        Some(self.travel(child))
+    }
+
+    /// Moves back through revisions in the order they were made, regardless of branch.
+    pub fn earlier(&mut self, step: Step) -> Option<Travel> {
+        let target = match step {
+            Step::Revisions(n) => self.current.saturating_sub(n),
+            Step::Time(duration) => match self.revisions[self.current].time.checked_sub(duration) {
+                Some(time) => self
+                    .revisions
+                    .iter()
+                    .rposition(|r| r.time <= time)
+                    .unwrap_or(0),
+                None => 0,
+            },
+        };
+        (target != self.current).then(|| self.travel(target))
+    }
+
+    /// Moves forward through revisions in the order they were made, regardless of branch.
+    pub fn later(&mut self, step: Step) -> Option<Travel> {
+        let last = self.revisions.len() - 1;
+        let target = match step {
+            Step::Revisions(n) => (self.current + n).min(last),
+            Step::Time(duration) => match self.revisions[self.current].time.checked_add(duration) {
+                Some(time) => self
+                    .revisions
+                    .iter()
+                    .position(|r| r.time >= time)
+                    .unwrap_or(last),
+                None => last,
+            },
+        };
+        (target != self.current).then(|| self.travel(target))
+    }
+
+    /// Moves to an arbitrary revision, undoing up to the common ancestor and redoing down the
+    /// target's branch.
+    pub fn goto(&mut self, revision: usize) -> Option<Travel> {
+        (revision < self.revisions.len() && revision != self.current).then(|| self.travel(revision))
+    }
+
+    fn ancestors(&self, mut revision: usize) -> Vec<usize> {
+        let mut path = vec![revision];
+        while revision != 0 {
+            revision = self.revisions[revision].parent;
+            path.push(revision);
+        }
+        path
+    }
+
+    fn travel(&mut self, target: usize) -> Travel {
+        self.commit();
+
+        let up = self.ancestors(self.current);
+        let mut down = self.ancestors(target);
+        let common = *up.iter().find(|r| down.contains(r)).unwrap();
+        down.truncate(down.iter().position(|&r| r == common).unwrap());
+
+        let mut edits = Vec::new();
+        let mut selections = self.revisions[self.current].transaction.after.clone();
+        for &revision in up.iter().take_while(|&&r| r != common) {
+            let transaction = &self.revisions[revision].transaction;
+            edits.extend(transaction.edits.iter().rev().map(Edit::inverse));
+            selections = transaction.before.clone();
+        }
+        for &revision in down.iter().rev() {
+            let transaction = &self.revisions[revision].transaction;
+            edits.extend(transaction.edits.iter().cloned());
+            selections = transaction.after.clone();
+            let parent = self.revisions[revision].parent;
+            self.revisions[parent].last_child = Some(revision);
+        }
+
+        self.current = target;
+        Travel { edits, selections }
+    }
+
+    /// Every revision, in the order they were made. Revision 0 is the unmodified document.
+    pub fn revisions(&self) -> Vec<RevisionInfo> {
+        let now = Instant::now();
+        self.revisions
+            .iter()
+            .map(|r| RevisionInfo {
+                parent: r.parent,
+                description: match r.transaction.edits.is_empty() {
+                    true => "original".to_string(),
+                    false => r.transaction.describe(),
+                },
+                age: now.duration_since(r.time),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+use crate::Selection;
+
+#[cfg(test)]
+fn type_str(document: &mut super::Document, s: &str) {
+    for c in s.chars() {
+        match c {
+            '\n' => document.insert('\n'),
+            '\x08' => document.delete(),
+            c => document.insert(c),
+        }
+    }
+}
+
+#[test]
+fn undo_round_trips() {
+    let mut document = super::Document::new();
+    type_str(&mut document, "hello\nworld");
+    document.commit();
+    type_str(&mut document, "\x08\x08\x08");
+    type_str(&mut document, "ms\n");
+    document.commit();
+    type_str(&mut document, "!\x08?");
+
+    assert_eq!(document.text(), "hello\nwoms\n?");
+    while document.undo() {}
+    assert_eq!(document.text(), "");
+    assert_eq!(document.selection(), Selection::point(0));
+
+    while document.redo() {}
+    assert_eq!(document.text(), "hello\nwoms\n?");
+    assert_eq!(document.selection(), Selection::point(12));
+}
+
+#[test]
+fn consecutive_inserts_are_grouped() {
+    let mut document = super::Document::new();
+    type_str(&mut document, "one\ntwo");
+    document.commit();
+    type_str(&mut document, " three");
+
+    assert!(document.undo());
+    assert_eq!(document.text(), "one\ntwo");
+    assert_eq!(document.selection(), Selection::point(7));
+
+    assert!(document.undo());
+    assert_eq!(document.text(), "");
+    assert!(!document.undo());
+
+    assert!(document.redo());
+    assert_eq!(document.text(), "one\ntwo");
+}
+
+#[test]
+fn editing_after_undo_starts_branch() {
+    let mut document = super::Document::new();
+    type_str(&mut document, "abc");
+    document.undo();
+    type_str(&mut document, "x");
+    assert!(!document.redo());
+    assert_eq!(document.text(), "x");
+}
+
+#[test]
+fn undone_branch_stays_reachable() {
+    let mut document = super::Document::new();
+    type_str(&mut document, "abc");
+    document.undo();
+    type_str(&mut document, "xyz");
+    assert_eq!(document.text(), "xyz");
+
+    // Revision 1 is "abc", on the branch abandoned by the undo
+    assert!(document.goto_revision(1));
+    assert_eq!(document.text(), "abc");
+    assert_eq!(document.selection(), Selection::point(3));
+
+    assert!(document.earlier(Step::Revisions(1)));
+    assert_eq!(document.text(), "");
+    assert!(document.later(Step::Revisions(2)));
+    assert_eq!(document.text(), "xyz");
+
+    // Redo follows the branch that was visited last
+    assert!(document.goto_revision(1));
+    document.undo();
+    assert!(document.redo());
+    assert_eq!(document.text(), "abc");
+}
+
+#[test]
+fn earlier_by_time() {
+    let mut document = super::Document::new();
+    type_str(&mut document, "old");
+    document.commit();
+    std::thread::sleep(Duration::from_millis(50));
+    type_str(&mut document, " new");
+
+    assert!(document.earlier(Step::Time(Duration::from_millis(25))));
+    assert_eq!(document.text(), "old");
+    assert!(document.later(Step::Time(Duration::from_millis(25))));
+    assert_eq!(document.text(), "old new");
+
+    // Further than an `Instant` can go just means all the way
+    let forever = Duration::from_secs(200_000_000_000_000 * 24 * 60 * 60);
+    assert!(document.earlier(Step::Time(forever)));
+    assert_eq!(document.text(), "");
+    assert!(document.later(Step::Time(forever)));
+    assert_eq!(document.text(), "old new");
+}
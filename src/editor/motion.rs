@@ -0,0 +1,210 @@
+use ropey::Rope;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+
+// Cursor positions are char indices, but the cursor only ever stops on grapheme boundaries so
+// that combining marks, emoji sequences and the like move as a single character.
+
+/// The grapheme boundary before `idx`, or 0.
+pub fn prev_grapheme(text: &Rope, idx: usize) -> usize {
+    let byte_idx = text.char_to_byte(idx);
+    let (mut chunk, mut chunk_byte, mut chunk_char, _) = text.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, text.len_bytes(), true);
+    loop {
+        match cursor.prev_boundary(chunk, chunk_byte) {
+            Ok(None) => return 0,
+            Ok(Some(n)) => return chunk_char + chunk[..n - chunk_byte].chars().count(),
+            Err(GraphemeIncomplete::PrevChunk) => {
+                (chunk, chunk_byte, chunk_char, _) = text.chunk_at_byte(chunk_byte - 1);
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let context = text.chunk_at_byte(n - 1).0;
+                cursor.provide_context(context, n - context.len());
+            }
+            Err(e) => unreachable!("{:?}", e),
+        }
+    }
+}
+
+/// The grapheme boundary after `idx`, or the end of the text.
+pub fn next_grapheme(text: &Rope, idx: usize) -> usize {
+    let byte_idx = text.char_to_byte(idx);
+    let (mut chunk, mut chunk_byte, mut chunk_char, _) = text.chunk_at_byte(byte_idx);
+    let mut cursor = GraphemeCursor::new(byte_idx, text.len_bytes(), true);
+    loop {
+        match cursor.next_boundary(chunk, chunk_byte) {
+            Ok(None) => return text.len_chars(),
+            Ok(Some(n)) => return chunk_char + chunk[..n - chunk_byte].chars().count(),
+            Err(GraphemeIncomplete::NextChunk) => {
+                chunk_byte += chunk.len();
+                chunk_char += chunk.chars().count();
+                chunk = text.chunk_at_byte(chunk_byte).0;
+            }
+            Err(GraphemeIncomplete::PreContext(n)) => {
+                let context = text.chunk_at_byte(n - 1).0;
+                cursor.provide_context(context, n - context.len());
+            }
+            Err(e) => unreachable!("{:?}", e),
+        }
+    }
+}
+
+/// Char index of the end of `line`, before its line break.
+pub fn line_end(text: &Rope, line: usize) -> usize {
+    let start = text.line_to_char(line);
+    let len = text.line(line).len_chars();
+    match len > 0 && text.char(start + len - 1) == '\n' {
+        true => start + len - 1,
+        false => start + len,
+    }
+}
+
+/// Number of graphemes between the start of the line and `idx`.
+pub fn column(text: &Rope, idx: usize) -> usize {
+    let mut pos = text.line_to_char(text.char_to_line(idx));
+    let mut column = 0;
+    while pos < idx {
+        pos = next_grapheme(text, pos);
+        column += 1;
+    }
+    column
+}
+
+/// Char index `column` graphemes into `line`, or the end of the line if it's shorter.
+pub fn at_column(text: &Rope, line: usize, column: usize) -> usize {
+    let end = line_end(text, line);
+    let mut pos = text.line_to_char(line);
+    for _ in 0..column {
+        if pos >= end {
+            break;
+        }
+        pos = next_grapheme(text, pos);
+    }
+    pos.min(end)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+pub fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Whitespace
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Start of the next word: past the rest of the current word or run of punctuation, then past
+/// any whitespace.
+pub fn next_word(text: &Rope, idx: usize) -> usize {
+    let len = text.len_chars();
+    let mut chars = text.chars_at(idx).peekable();
+    let mut pos = idx;
+    if let Some(&first) = chars.peek() {
+        let first = class(first);
+        while matches!(chars.peek(), Some(&c) if first != CharClass::Whitespace && class(c) == first)
+        {
+            chars.next();
+            pos += 1;
+        }
+    }
+    while matches!(chars.peek(), Some(&c) if class(c) == CharClass::Whitespace) {
+        chars.next();
+        pos += 1;
+    }
+    pos.min(len)
+}
+
+/// Start of the word before the cursor, skipping whitespace first.
+pub fn prev_word(text: &Rope, idx: usize) -> usize {
+    let mut chars = text.chars_at(idx);
+    let mut pos = idx;
+    let mut c = chars.prev();
+    while matches!(c, Some(c) if class(c) == CharClass::Whitespace) {
+        pos -= 1;
+        c = chars.prev();
+    }
+    if let Some(first) = c.map(class) {
+        while matches!(c, Some(c) if class(c) == first) {
+            pos -= 1;
+            c = chars.prev();
+        }
+    }
+    pos
+}
+
+pub fn is_blank(text: &Rope, line: usize) -> bool {
+    text.line(line).chars().all(char::is_whitespace)
+}
+
+/// Start of the next blank line after the current paragraph, or the end of the text.
+pub fn next_paragraph(text: &Rope, idx: usize) -> usize {
+    let lines = text.len_lines();
+    let mut line = text.char_to_line(idx);
+    while line < lines && is_blank(text, line) {
+        line += 1;
+    }
+    while line < lines && !is_blank(text, line) {
+        line += 1;
+    }
+    match line < lines {
+        true => text.line_to_char(line),
+        false => text.len_chars(),
+    }
+}
+
+/// Start of the blank line before the current paragraph, or the start of the text.
+pub fn prev_paragraph(text: &Rope, idx: usize) -> usize {
+    let mut line = text.char_to_line(idx);
+    // From the first line of a paragraph, look for the one before it
+    if line > 0 && text.line_to_char(line) == idx {
+        line -= 1;
+    }
+    while line > 0 && is_blank(text, line) {
+        line -= 1;
+    }
+    while line > 0 && !is_blank(text, line) {
+        line -= 1;
+    }
+    text.line_to_char(line)
+}
+
+#[test]
+fn graphemes() {
+    // e + combining acute, then a family emoji made of several chars
+    let text = Rope::from_str("ae\u{301}\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}b");
+    let mut stops = vec![0];
+    while *stops.last().unwrap() < text.len_chars() {
+        stops.push(next_grapheme(&text, *stops.last().unwrap()));
+    }
+    assert_eq!(stops, [0, 1, 3, 8, 9]);
+
+    let mut back = vec![9];
+    while *back.last().unwrap() > 0 {
+        back.push(prev_grapheme(&text, *back.last().unwrap()));
+    }
+    assert_eq!(back, [9, 8, 3, 1, 0]);
+    assert_eq!(column(&text, 8), 3);
+}
+
+#[test]
+fn words_and_paragraphs() {
+    let text = Rope::from_str("foo.bar  baz\n\nnext para\nsame\n\nlast");
+    assert_eq!(next_word(&text, 0), 3);
+    assert_eq!(next_word(&text, 3), 4);
+    assert_eq!(next_word(&text, 4), 9);
+    assert_eq!(prev_word(&text, 9), 4);
+    assert_eq!(prev_word(&text, 4), 3);
+
+    assert_eq!(next_paragraph(&text, 0), 13);
+    assert_eq!(next_paragraph(&text, 13), 29);
+    assert_eq!(next_paragraph(&text, 29), text.len_chars());
+    assert_eq!(prev_paragraph(&text, text.len_chars()), 29);
+    assert_eq!(prev_paragraph(&text, 29), 13);
+    assert_eq!(prev_paragraph(&text, 13), 0);
+}
@@ -0,0 +1,242 @@
+use super::motion::{self, CharClass};
+use ropey::Rope;
+use std::ops::Range;
+
+// Text objects: ranges of text around a position or a selection, for selecting and for
+// operators to act on. Brackets and quotes look at the whole selection so that selecting them
+// again grows the selection outwards: first the inside of the pair, then the pair itself, then
+// the next pair out.
+
+const BRACKETS: [(char, char); 4] = [('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')];
+const QUOTES: [char; 3] = ['"', '\'', '`'];
+
+/// The word, run of punctuation or run of whitespace at `idx`, with any whitespace after it.
+pub fn word(text: &Rope, idx: usize) -> Range<usize> {
+    let mut start = idx;
+    if let Some(first) = text.get_char(idx).map(motion::class) {
+        let mut chars = text.chars_at(idx);
+        while matches!(chars.prev(), Some(c) if motion::class(c) == first) {
+            start -= 1;
+        }
+    }
+    start..motion::next_word(text, start)
+}
+
+/// The lines of the paragraph at `idx` and the blank lines after it. From a blank line, the
+/// blank lines and the paragraph after them.
+pub fn paragraph(text: &Rope, idx: usize) -> Range<usize> {
+    let lines = text.len_lines();
+    let mut start = text.char_to_line(idx);
+    let mut end = start;
+    if motion::is_blank(text, start) {
+        while end < lines && motion::is_blank(text, end) {
+            end += 1;
+        }
+        while end < lines && !motion::is_blank(text, end) {
+            end += 1;
+        }
+    } else {
+        while start > 0 && !motion::is_blank(text, start - 1) {
+            start -= 1;
+        }
+        while end < lines && !motion::is_blank(text, end) {
+            end += 1;
+        }
+        while end < lines && motion::is_blank(text, end) {
+            end += 1;
+        }
+    }
+    text.line_to_char(start)..text.line_to_char(end)
+}
+
+/// The inside of the innermost bracket pair around `selection`, or the pair itself if the
+/// inside is already selected.
+pub fn brackets(text: &Rope, selection: Range<usize>) -> Option<Range<usize>> {
+    let mut from = selection.start;
+    // On an opening bracket means that pair, not the one around it
+    if selection.is_empty() && matches!(text.get_char(from), Some(c) if kind(c, 0).is_some()) {
+        from += 1;
+    }
+
+    loop {
+        let open = unmatched_open(text, from)?;
+        // One that's never closed doesn't stop the pairs around it
+        let pair = matching_close(text, open).map(|close| open..close + 1);
+        if let Some(range) = pair.and_then(|pair| around(pair, &selection)) {
+            return Some(range);
+        }
+        from = open;
+    }
+}
+
+/// The inside of the quoted string around `selection` on the line, or the string with its
+/// quotes if the inside is already selected.
+pub fn quotes(text: &Rope, selection: Range<usize>) -> Option<Range<usize>> {
+    let line = text.char_to_line(selection.start);
+    let line_start = text.line_to_char(line);
+    let mut open = None;
+    let mut escaped = false;
+    for (idx, c) in (line_start..).zip(text.line(line).chars()) {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match open {
+            _ if c == '\\' => escaped = true,
+            None if QUOTES.contains(&c) => open = Some((c, idx)),
+            Some((quote, start)) if c == quote => {
+                if let Some(range) = around(start..idx + 1, &selection) {
+                    return Some(range);
+                }
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The lines around `idx` indented at least as far as its line, without blank lines at either
+/// end.
+pub fn indent(text: &Rope, idx: usize) -> Range<usize> {
+    let lines = text.len_lines();
+    let line = text.char_to_line(idx);
+    let level = indentation(text, line);
+    let inside = |line| motion::is_blank(text, line) || indentation(text, line) >= level;
+
+    let mut start = line;
+    while start > 0 && inside(start - 1) {
+        start -= 1;
+    }
+    let mut end = line + 1;
+    while end < lines && inside(end) {
+        end += 1;
+    }
+
+    while start < line && motion::is_blank(text, start) {
+        start += 1;
+    }
+    while end > line + 1 && motion::is_blank(text, end - 1) {
+        end -= 1;
+    }
+    text.line_to_char(start)..text.line_to_char(end)
+}
+
+fn indentation(text: &Rope, line: usize) -> usize {
+    text.line(line)
+        .chars()
+        .take_while(|&c| c != '\n' && motion::class(c) == CharClass::Whitespace)
+        .count()
+}
+
+/// What to select given a pair of delimiters at the ends of `pair`: the inside if it contains
+/// the selection, or the pair itself if the inside is all that's selected. A cursor on either
+/// delimiter counts as inside.
+fn around(pair: Range<usize>, selection: &Range<usize>) -> Option<Range<usize>> {
+    let inner = pair.start + 1..pair.end - 1;
+    let contains =
+        |range: &Range<usize>| range.start <= selection.start && selection.end <= range.end;
+    let on_pair = selection.is_empty() && contains(&pair) && selection.start < pair.end;
+    if on_pair || (contains(&inner) && inner != *selection) {
+        Some(inner)
+    } else if contains(&pair) && pair != *selection {
+        Some(pair)
+    } else {
+        None
+    }
+}
+
+/// Which of `BRACKETS` `c` is, if it's the opening (`side` 0) or closing (`side` 1) one.
+fn kind(c: char, side: usize) -> Option<usize> {
+    BRACKETS
+        .iter()
+        .position(|&(open, close)| [open, close][side] == c)
+}
+
+/// The last opening bracket before `idx` that isn't closed before `idx`.
+fn unmatched_open(text: &Rope, idx: usize) -> Option<usize> {
+    let mut depth = [0; BRACKETS.len()];
+    let mut chars = text.chars_at(idx);
+    let mut pos = idx;
+    while let Some(c) = chars.prev() {
+        pos -= 1;
+        if let Some(kind) = kind(c, 1) {
+            depth[kind] += 1;
+        } else if let Some(kind) = kind(c, 0) {
+            match depth[kind] {
+                0 => return Some(pos),
+                _ => depth[kind] -= 1,
+            }
+        }
+    }
+    None
+}
+
+/// The bracket closing the one at `open`.
+fn matching_close(text: &Rope, open: usize) -> Option<usize> {
+    let (opening, closing) = BRACKETS[kind(text.char(open), 0)?];
+    let mut depth = 0;
+    for (pos, c) in (open + 1..).zip(text.chars_at(open + 1)) {
+        if c == opening {
+            depth += 1;
+        } else if c == closing {
+            match depth {
+                0 => return Some(pos),
+                _ => depth -= 1,
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn words_and_paragraphs() {
+    let text = Rope::from_str("foo.bar  baz\n\nnext para\nsame\n\nlast");
+    assert_eq!(word(&text, 1), 0..3);
+    assert_eq!(word(&text, 5), 4..9);
+    assert_eq!(word(&text, 7), 7..9);
+    assert_eq!(word(&text, text.len_chars()), 34..34);
+
+    assert_eq!(paragraph(&text, 2), 0..14);
+    assert_eq!(paragraph(&text, 26), 14..30);
+    assert_eq!(paragraph(&text, 13), 13..29);
+    assert_eq!(paragraph(&text, 31), 30..text.len_chars());
+}
+
+#[test]
+fn brackets_grow_outwards() {
+    let text = Rope::from_str("f(a, [b], (c d))");
+    assert_eq!(brackets(&text, 12..12), Some(11..14));
+    assert_eq!(brackets(&text, 11..14), Some(10..15));
+    assert_eq!(brackets(&text, 10..15), Some(2..15));
+    assert_eq!(brackets(&text, 2..15), Some(1..16));
+    assert_eq!(brackets(&text, 1..16), None);
+
+    // On a bracket
+    assert_eq!(brackets(&text, 5..5), Some(6..7));
+    assert_eq!(brackets(&text, 7..7), Some(6..7));
+    assert_eq!(brackets(&text, 0..0), None);
+
+    // Past a bracket that's never closed
+    let text = Rope::from_str("(a [b c)");
+    assert_eq!(brackets(&text, 5..5), Some(1..7));
+}
+
+#[test]
+fn quoted_strings() {
+    let text = Rope::from_str(r#"say "hi \"x\"" and 'y'"#);
+    assert_eq!(quotes(&text, 6..6), Some(5..13));
+    assert_eq!(quotes(&text, 5..13), Some(4..14));
+    assert_eq!(quotes(&text, 4..14), None);
+    assert_eq!(quotes(&text, 4..4), Some(5..13));
+    assert_eq!(quotes(&text, 20..20), Some(20..21));
+    assert_eq!(quotes(&text, 15..15), None);
+}
+
+#[test]
+fn indentation_blocks() {
+    let text = Rope::from_str("fn a() {\n    one\n\n    two\n        three\n}\n");
+    assert_eq!(indent(&text, 20), 9..40);
+    assert_eq!(indent(&text, 30), 26..40);
+    assert_eq!(indent(&text, 0), 0..text.len_chars());
+}
@@ -0,0 +1,471 @@
+use crate::Direction;
+use regex_automata::{
+    hybrid::dfa::{Cache, DFA},
+    meta::Regex,
+    nfa::thompson,
+    util::{captures::Captures, start, syntax},
+    Anchored, Input, MatchKind,
+};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// Regex search straight over a rope's chunks, so that big files never get copied into a string.
+// A lazy DFA is fed the text a byte at a time: one running forwards finds where the leftmost
+// match ends, then one running backwards from there finds where it starts. Only capture groups
+// need the full regex engine, and that runs over the text of the match alone.
+//
+// The DFAs can't handle Unicode word boundaries (`\b`) in non-ASCII text. When they give up, the
+// rest of what's being searched is copied out and searched with the full engine instead.
+
+/// The current search, for showing how far through the matches the cursor is and highlighting
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchStatus {
+    pub pattern: String,
+    /// Which match is selected, counting from 0, if one is.
+    pub current: Option<usize>,
+    pub total: usize,
+}
+
+/// A line that a search and replace would change, as it is and as it would be.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplacedLine {
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// The lazy DFA couldn't go on.
+struct GaveUp;
+
+/// A search pattern, a regex where `^` and `$` match at line breaks. Case is ignored unless the
+/// pattern has upper case letters in it.
+pub struct Matcher {
+    source: String,
+    forward: DFA,
+    forward_cache: Cache,
+    /// Matches the pattern reversed, for finding where a match starts from where it ends.
+    reverse: DFA,
+    reverse_cache: Cache,
+    regex: Regex,
+    captures: Captures,
+}
+
+impl Matcher {
+    pub fn new(pattern: &str) -> anyhow::Result<Self> {
+        let syntax = syntax::Config::new()
+            .case_insensitive(!has_upper_case(pattern))
+            .multi_line(true)
+            .crlf(true);
+        let forward = DFA::builder()
+            .configure(DFA::config().unicode_word_boundary(true))
+            .syntax(syntax)
+            .build(pattern)?;
+        let reverse = DFA::builder()
+            .configure(
+                DFA::config()
+                    .unicode_word_boundary(true)
+                    .match_kind(MatchKind::All),
+            )
+            .syntax(syntax)
+            .thompson(thompson::Config::new().reverse(true))
+            .build(pattern)?;
+        let regex = Regex::builder().syntax(syntax).build(pattern)?;
+
+        Ok(Self {
+            source: pattern.to_string(),
+            forward_cache: forward.create_cache(),
+            forward,
+            reverse_cache: reverse.create_cache(),
+            reverse,
+            captures: regex.create_captures(),
+            regex,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.source
+    }
+
+    /// Matches that lie within `range`, in order and not overlapping.
+    pub fn matches<'a>(
+        &'a mut self,
+        text: &'a Rope,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = Range<usize>> + 'a {
+        let matches = Matches {
+            at: text.char_to_byte(range.start),
+            end: text.char_to_byte(range.end),
+            last_end: None,
+            copied: None,
+            matcher: self,
+            text,
+        };
+        matches.map(|found| text.byte_to_char(found.start)..text.byte_to_char(found.end))
+    }
+
+    /// Next match strictly after (or before) char index `from`, wrapping around the end of the
+    /// text. Returns the match as char indices, and whether the search wrapped. Empty matches
+    /// don't count.
+    pub fn find(
+        &mut self,
+        text: &Rope,
+        from: usize,
+        direction: Direction,
+    ) -> Option<(Range<usize>, bool)> {
+        let len = text.len_chars();
+        let found = match direction {
+            Direction::Forward => self
+                .matches(text, (from + 1).min(len)..len)
+                .find(|found| !found.is_empty()),
+            Direction::Backward => self
+                .matches(text, 0..from)
+                .filter(|found| !found.is_empty())
+                .last(),
+        };
+        if let Some(found) = found {
+            return Some((found, false));
+        }
+
+        let mut all = self.matches(text, 0..len).filter(|found| !found.is_empty());
+        let found = match direction {
+            Direction::Forward => all.next()?,
+            Direction::Backward => all.last()?,
+        };
+        Some((found, true))
+    }
+
+    /// Where `selected` is among all the matches.
+    pub fn status(&mut self, text: &Rope, selected: Range<usize>) -> SearchStatus {
+        let mut current = None;
+        let mut total = 0;
+        for found in self.matches(text, 0..text.len_chars()) {
+            if found.is_empty() {
+                continue;
+            }
+            if found == selected {
+                current = Some(total);
+            }
+            total += 1;
+        }
+        SearchStatus {
+            pattern: self.source.clone(),
+            current,
+            total,
+        }
+    }
+
+    /// `replacement` with `$1`, `${name}` and so on replaced by the groups captured by the match
+    /// at `found`, and `$$` by `$`.
+    pub fn expand(&mut self, text: &Rope, found: Range<usize>, replacement: &str) -> String {
+        // With a char either side for `\b`, `^` and `$` to look at
+        let start = found.start.saturating_sub(1);
+        let end = (found.end + 1).min(text.len_chars());
+        let haystack = text.slice(start..end).to_string();
+        let offset = text.char_to_byte(start);
+        let span = text.char_to_byte(found.start) - offset..text.char_to_byte(found.end) - offset;
+
+        let input = Input::new(&haystack).span(span).anchored(Anchored::Yes);
+        self.regex.search_captures(&input, &mut self.captures);
+        self.captures.interpolate_string(&haystack, replacement)
+    }
+
+    /// The leftmost match starting at or after byte `at` and ending by byte `end`, as bytes.
+    fn search(
+        &mut self,
+        text: &Rope,
+        at: usize,
+        end: usize,
+    ) -> Result<Option<Range<usize>>, GaveUp> {
+        let match_end = match self.search_forward(text, at, end)? {
+            Some(match_end) => match_end,
+            None => return Ok(None),
+        };
+        let match_start = self.search_reverse(text, at, match_end)?;
+        Ok(Some(match_start..match_end))
+    }
+
+    /// Where the leftmost match in `at..end` ends.
+    fn search_forward(
+        &mut self,
+        text: &Rope,
+        at: usize,
+        end: usize,
+    ) -> Result<Option<usize>, GaveUp> {
+        let (dfa, cache) = (&self.forward, &mut self.forward_cache);
+        let config = start::Config::new()
+            .anchored(Anchored::No)
+            .look_behind(at.checked_sub(1).map(|idx| text.byte(idx)));
+        let mut state = dfa.start_state(cache, &config).map_err(|_| GaveUp)?;
+
+        // Matches show up a byte late, so this looks one past `end` for a match ending there
+        let mut bytes = text.bytes_at(at);
+        let mut found = None;
+        for pos in at..=end {
+            state = match bytes.next() {
+                Some(byte) => dfa.next_state(cache, state, byte),
+                None => dfa.next_eoi_state(cache, state),
+            }
+            .map_err(|_| GaveUp)?;
+
+            if state.is_match() {
+                found = Some(pos);
+            } else if state.is_dead() {
+                break;
+            } else if state.is_quit() {
+                return Err(GaveUp);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Where the match ending at `end` starts, which is no earlier than `at`.
+    fn search_reverse(&mut self, text: &Rope, at: usize, end: usize) -> Result<usize, GaveUp> {
+        let (dfa, cache) = (&self.reverse, &mut self.reverse_cache);
+        let config = start::Config::new()
+            .anchored(Anchored::Yes)
+            .look_behind(text.get_byte(end));
+        let mut state = dfa.start_state(cache, &config).map_err(|_| GaveUp)?;
+
+        let mut bytes = text.bytes_at(end);
+        let mut found = end;
+        for pos in (at..=end).rev() {
+            state = match bytes.prev() {
+                Some(byte) => dfa.next_state(cache, state, byte),
+                None => dfa.next_eoi_state(cache, state),
+            }
+            .map_err(|_| GaveUp)?;
+
+            if state.is_match() {
+                found = pos;
+            } else if state.is_dead() {
+                break;
+            } else if state.is_quit() {
+                return Err(GaveUp);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Matches in a range of a rope, as bytes.
+struct Matches<'a> {
+    matcher: &'a mut Matcher,
+    text: &'a Rope,
+    at: usize,
+    end: usize,
+    /// Where the last match ended, as an empty match can't come straight after a match.
+    last_end: Option<usize>,
+    /// Text copied from the rope once the DFAs gave up, with the byte in the rope it starts at.
+    copied: Option<(String, usize)>,
+}
+
+impl Iterator for Matches<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        while self.at <= self.end {
+            let found = self.search()?;
+            if found.is_empty() && Some(found.start) == self.last_end {
+                // Try again from the next char
+                self.at = match self.text.byte_to_char(found.start) {
+                    idx if idx < self.text.len_chars() => self.text.char_to_byte(idx + 1),
+                    _ => self.end + 1,
+                };
+                continue;
+            }
+            self.at = found.end;
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+        None
+    }
+}
+
+impl Matches<'_> {
+    fn search(&mut self) -> Option<Range<usize>> {
+        if self.copied.is_none() {
+            match self.matcher.search(self.text, self.at, self.end) {
+                Ok(found) => return found,
+                Err(GaveUp) => {
+                    // With a char either side to look at
+                    let start = self.text.byte_to_char(self.at).saturating_sub(1);
+                    let end = (self.text.byte_to_char(self.end) + 1).min(self.text.len_chars());
+                    let copied = self.text.slice(start..end).to_string();
+                    self.copied = Some((copied, self.text.char_to_byte(start)));
+                }
+            }
+        }
+
+        let (copied, offset) = self.copied.as_ref().unwrap();
+        let input = Input::new(copied).span(self.at - offset..self.end - offset);
+        let found = self.matcher.regex.search(&input)?;
+        Some(found.start() + offset..found.end() + offset)
+    }
+}
+
+/// Whether a pattern has upper case letters in it, not counting escapes like `\W`.
+fn has_upper_case(pattern: &str) -> bool {
+    let mut escaped = false;
+    pattern.chars().any(|c| {
+        let upper = !escaped && c.is_uppercase();
+        escaped = !escaped && c == '\\';
+        upper
+    })
+}
+
+/// The lines that making `replacements` would change. Replacements are sorted and don't overlap.
+pub fn preview(text: &Rope, replacements: &[(Range<usize>, String)]) -> Vec<ReplacedLine> {
+    let mut lines = Vec::new();
+    let mut replacements = replacements.iter().peekable();
+    while let Some((first, _)) = replacements.peek() {
+        let line = text.char_to_line(first.start);
+        let start = text.line_to_char(line);
+        let mut last_line = line;
+        let mut after = String::new();
+        let mut pos = start;
+        // Replacements spanning lines take the lines they end on with them
+        while let Some((range, with)) =
+            replacements.next_if(|(range, _)| text.char_to_line(range.start) <= last_line)
+        {
+            after.extend(text.slice(pos..range.start).chars());
+            after.push_str(with);
+            pos = range.end;
+            last_line = text.char_to_line(range.end);
+        }
+
+        let end = text.line_to_char((last_line + 1).min(text.len_lines()));
+        after.extend(text.slice(pos..end).chars());
+        let before = text.slice(start..end).to_string();
+        lines.push(ReplacedLine {
+            line,
+            before: before.trim_end_matches(['\r', '\n']).to_string(),
+            after: after.trim_end_matches(['\r', '\n']).to_string(),
+        });
+    }
+    lines
+}
+
+#[test]
+fn find_wraps() {
+    let text = Rope::from_str("one two one two");
+    let find =
+        |pattern, from, direction| Matcher::new(pattern).unwrap().find(&text, from, direction);
+    assert_eq!(find("two", 0, Direction::Forward), Some((4..7, false)));
+    assert_eq!(find("two", 4, Direction::Forward), Some((12..15, false)));
+    assert_eq!(find("two", 12, Direction::Forward), Some((4..7, true)));
+    assert_eq!(find("one", 8, Direction::Backward), Some((0..3, false)));
+    assert_eq!(find("one", 0, Direction::Backward), Some((8..11, true)));
+    assert_eq!(find("three", 0, Direction::Forward), None);
+}
+
+#[test]
+fn smart_case() {
+    let text = Rope::from_str("Rope rope ROPE");
+    let starts = |pattern| {
+        let mut matcher = Matcher::new(pattern).unwrap();
+        let starts: Vec<_> = matcher
+            .matches(&text, 0..text.len_chars())
+            .map(|found| found.start)
+            .collect();
+        starts
+    };
+    assert_eq!(starts("rope"), [0, 5, 10]);
+    assert_eq!(starts("Rope"), [0]);
+    assert_eq!(starts("ROPE"), [10]);
+    assert_eq!(starts(r"\Wrope"), [4, 9]);
+
+    let status = Matcher::new("rope").unwrap().status(&text, 5..9);
+    assert_eq!((status.current, status.total), (Some(1), 3));
+}
+
+#[test]
+fn regexes_across_chunks() {
+    // Long enough to take many chunks, with matches straddling them
+    let source: String = (0..2000)
+        .map(|i| format!("línea {} fn item_{}() {{}}\n", i, i))
+        .collect();
+    let text = Rope::from_str(&source);
+    assert!(text.chunks().count() > 10);
+
+    let found = |pattern: &str, range: Range<usize>| {
+        let mut matcher = Matcher::new(pattern).unwrap();
+        let found: Vec<_> = matcher.matches(&text, range).collect();
+        found
+    };
+    let expected = |pattern: &str| {
+        let regex = Regex::builder()
+            .syntax(syntax::Config::new().multi_line(true).crlf(true))
+            .build(pattern)
+            .unwrap();
+        let chars = |byte| source[..byte].chars().count();
+        let expected: Vec<_> = regex
+            .find_iter(&source)
+            .map(|found| chars(found.start())..chars(found.end()))
+            .collect();
+        expected
+    };
+
+    let all = 0..text.len_chars();
+    for pattern in [
+        r"fn item_\d+",
+        r"^línea 1\d*",
+        r"\(\)\s*\{\}$",
+        r"\bitem_19\b",
+        r"\d+ fn",
+    ] {
+        assert_eq!(
+            found(pattern, all.clone()),
+            expected(pattern),
+            "{}",
+            pattern
+        );
+    }
+
+    // Matches are cut short at either end of the range
+    let line = text.line_to_char(15);
+    assert_eq!(
+        found(r"\d+", line + 7..line + 18),
+        [line + 7..line + 8, line + 17..line + 18]
+    );
+
+    // Empty matches, but not straight after another match
+    assert_eq!(found("x*", 0..3), [0..0, 1..1, 2..2, 3..3]);
+    let text = Rope::from_str("axxb");
+    let mut matcher = Matcher::new("x*").unwrap();
+    let found: Vec<_> = matcher.matches(&text, 0..4).collect();
+    assert_eq!(found, [0..0, 1..3, 4..4]);
+}
+
+#[test]
+fn captures_and_preview() {
+    let text = Rope::from_str("let a = f(1);\nlet bb = g(22);\nnothing\n");
+    let mut matcher = Matcher::new(r"(\w+)\((?<arg>\d+)\)").unwrap();
+    let found: Vec<_> = matcher.matches(&text, 0..text.len_chars()).collect();
+    let replacements: Vec<_> = found
+        .into_iter()
+        .map(|found| {
+            let with = matcher.expand(&text, found.clone(), "${arg}.$1()$$");
+            (found, with)
+        })
+        .collect();
+    assert_eq!(replacements[0].1, "1.f()$");
+    assert_eq!(replacements[1].1, "22.g()$");
+
+    assert_eq!(
+        preview(&text, &replacements),
+        [
+            ReplacedLine {
+                line: 0,
+                before: "let a = f(1);".to_string(),
+                after: "let a = 1.f()$;".to_string(),
+            },
+            ReplacedLine {
+                line: 1,
+                before: "let bb = g(22);".to_string(),
+                after: "let bb = 22.g()$;".to_string(),
+            },
+        ]
+    );
+}
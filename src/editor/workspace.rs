@@ -0,0 +1,146 @@
+use super::encoding::Encoding;
+use super::file;
+use super::search::Matcher;
+use crossbeam_channel::Sender;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+// The files under the directory the editor was started in, skipping what .gitignore and friends
+// say to, and searching them all in the background.
+
+/// How much of a file to look at for NUL bytes, which mean it isn't text.
+const BINARY_CHECK_LEN: usize = 8192;
+
+/// A line with a match of a project-wide search on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub path: PathBuf,
+    /// Where the first match on the line starts, in chars from 0.
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+/// Progress of a search started with `grep`.
+#[derive(Debug)]
+pub enum GrepEvent {
+    /// Matches in one file.
+    Found {
+        id: usize,
+        hits: Vec<Hit>,
+    },
+    Done {
+        id: usize,
+        files: usize,
+    },
+}
+
+/// Stops a running search when dropped.
+pub struct Grep {
+    pub id: usize,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for Grep {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Files under `root`, sorted by path, leaving out hidden and ignored ones.
+pub fn files(root: &Path) -> impl Iterator<Item = PathBuf> {
+    ignore::WalkBuilder::new(root)
+        // Ignore files count outside of git repositories too
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                log::warn!("grep: {}", e);
+                None
+            }
+        })
+        .filter(|entry| matches!(entry.file_type(), Some(file_type) if file_type.is_file()))
+        .map(|entry| entry.into_path())
+}
+
+/// Searches every file under `root` for `pattern` on another thread, sending what it finds to
+/// `tx` tagged with `id`. The pattern is checked before anything starts.
+pub fn grep(
+    root: PathBuf,
+    pattern: &str,
+    id: usize,
+    tx: Sender<GrepEvent>,
+) -> anyhow::Result<Grep> {
+    let mut matcher = Matcher::new(pattern)?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let grep = Grep {
+        id,
+        cancelled: cancelled.clone(),
+    };
+
+    std::thread::Builder::new()
+        .name("grep".to_string())
+        .spawn(move || {
+            let mut searched = 0;
+            for path in files(&root) {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let hits = search_file(&mut matcher, &path);
+                searched += 1;
+                if !hits.is_empty() && tx.send(GrepEvent::Found { id, hits }).is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(GrepEvent::Done {
+                id,
+                files: searched,
+            });
+        })?;
+
+    Ok(grep)
+}
+
+/// The lines of the file at `path` with matches on them. Files that can't be read or aren't text
+/// have none.
+fn search_file(matcher: &mut Matcher, path: &Path) -> Vec<Hit> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::debug!("grep: skipping {} ({})", path.display(), e);
+            return Vec::new();
+        }
+    };
+    if bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0) {
+        return Vec::new();
+    }
+    // Decoded the same way as opening the file would, so positions line up
+    let (text, _) = Encoding::detect(&bytes).decode(&bytes);
+    let (text, _) = file::decode_lines(&text);
+
+    let mut hits: Vec<Hit> = Vec::new();
+    for found in matcher.matches(&text, 0..text.len_chars()) {
+        if found.is_empty() {
+            continue;
+        }
+        let line = text.char_to_line(found.start);
+        if matches!(hits.last(), Some(hit) if hit.line == line) {
+            continue;
+        }
+        let content = text.line(line).to_string();
+        hits.push(Hit {
+            path: path.to_owned(),
+            line,
+            column: found.start - text.line_to_char(line),
+            text: content.trim_end_matches(['\r', '\n']).to_string(),
+        });
+    }
+    hits
+}
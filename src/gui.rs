@@ -1,12 +1,23 @@
+mod fuzzy;
 mod input;
+mod keyboard;
+mod keymap;
+mod picker;
 mod render;
 mod types;
 mod window;
 
-use crate::{ClientMessage, Command, ServerMessage};
+use crate::{
+    editor::{search::Matcher, BufferInfo, DocumentId, ReplacedLine, SearchStatus},
+    ClientMessage, Command, Diagnostic, Selections, ServerMessage,
+};
 use crossbeam_channel::{Receiver, Sender};
-use render::Render;
-use types::{Key, KeyEvent, KeyState};
+use input::{Action, Input, Mode};
+use keymap::Keymap;
+use picker::Picker;
+use render::{Overlay, OverlayLine, Render};
+use std::{ops::Range, path::PathBuf};
+use types::KeyEvent;
 use window::{Window, WindowEvent};
 
 // What do we want from our UI?
@@ -31,6 +42,12 @@ use window::{Window, WindowEvent};
 // backend: Vulkan abstraction layer - APIs for pipelines, command buffers, descriptor sets?
 // render: Application-specific structures built with backend abstractions
 
+/// Most files the picker lists at once.
+const PICKER_HEIGHT: usize = 16;
+
+/// Most changed lines the replace preview shows at once, each as it is and as it would be.
+const PREVIEW_LINES: usize = 8;
+
 pub struct Gui {
     thread: Option<std::thread::JoinHandle<()>>,
     pub tx: Sender<ServerMessage>,
@@ -59,12 +76,34 @@ pub fn spawn() -> anyhow::Result<Gui> {
     })
 }
 
+#[allow(dead_code)]
 struct App {
     window: Window,
     render: Render,
     tx: Sender<ClientMessage>,
     rx: Receiver<ServerMessage>,
+
+    // Mirror of the server's view of the current document
     text: ropey::Rope,
+    selections: Selections,
+    status: String,
+    /// The search being typed or last searched for, with its matches highlighted.
+    search: Option<SearchStatus>,
+    /// The search's pattern compiled, kept while the pattern stays the same.
+    matcher: Option<Matcher>,
+    diagnostics: Vec<Diagnostic>,
+    buffers: Vec<BufferInfo>,
+    /// Not known until the server first lists the buffers.
+    current_buffer: Option<DocumentId>,
+    /// Open while picking a file, following the query typed in input's mode.
+    picker: Option<Picker>,
+    /// Lines the replacement being typed would change, shown while typing it.
+    replace_preview: Vec<ReplacedLine>,
+    /// The server asked whether to quit with unsaved changes; quitting again means yes.
+    confirming_quit: bool,
+    needs_redraw: bool,
+
+    input: Input,
 }
 
 fn run(tx: Sender<ClientMessage>, rx: Receiver<ServerMessage>) {
@@ -72,7 +111,7 @@ fn run(tx: Sender<ClientMessage>, rx: Receiver<ServerMessage>) {
     let mut render = Render::new(&window).unwrap();
     let text = ropey::Rope::new();
 
-    match render.draw_frame(&text) {
+    match render.draw_frame(&text, &Selections::default(), &[], None, "") {
         Ok(_) => {}
         Err(_) => panic!(),
     }
@@ -83,16 +122,55 @@ fn run(tx: Sender<ClientMessage>, rx: Receiver<ServerMessage>) {
         tx,
         rx,
         text,
+        selections: Selections::default(),
+        status: String::new(),
+        search: None,
+        matcher: None,
+        diagnostics: Vec::new(),
+        buffers: Vec::new(),
+        current_buffer: None,
+        picker: None,
+        replace_preview: Vec::new(),
+        confirming_quit: false,
+        needs_redraw: false,
+        input: Input::new(),
     };
 
+    match Keymap::load() {
+        Ok(keymap) => app.input = Input::with_keymap(keymap),
+        Err(e) => {
+            log::error!("{:#}", e);
+            app.status = format!("{:#}", e);
+        }
+    }
+
     'main_loop: loop {
+        // Waiting for the rest of a key sequence
+        let input_timeout = match app.input.deadline() {
+            Some(deadline) => crossbeam_channel::at(deadline),
+            None => crossbeam_channel::never(),
+        };
+
         crossbeam_channel::select! {
-            recv(app.rx) -> msg => {
-                log::trace!("client: received {:?}", msg);
-            }
+            recv(app.rx) -> msg => match msg {
+                Ok(msg) => {
+                    app.handle_server_message(msg);
+                    // Apply everything already queued before drawing a single frame
+                    while let Ok(msg) = app.rx.try_recv() {
+                        app.handle_server_message(msg);
+                    }
+                    app.redraw_if_needed().unwrap();
+                }
+                Err(e) => log::error!("server closed channel ({e})"),
+            },
             recv(app.window.rx) -> event => match event {
                 Ok(event) => if app.handle_window_event(event) { break 'main_loop },
                 Err(e) => log::error!("server closed channel ({e})"),
+            },
+            recv(input_timeout) -> _ => {
+                let mode = app.input.mode().clone();
+                let actions = app.input.timeout();
+                app.handle_actions(actions, mode).unwrap();
             }
         }
     }
@@ -107,7 +185,9 @@ impl App {
             WindowEvent::Quit => return true,
             WindowEvent::Resize(width, height, tx) => {
                 self.render.resize(width, height).unwrap();
-                self.render.draw_frame(&self.text).unwrap();
+                self.draw().unwrap();
+                let lines = self.render.lines_in(height);
+                self.command(Command::SetPageHeight(lines)).unwrap();
                 if let Some(tx) = tx {
                     tx.send(()).unwrap()
                 };
@@ -118,57 +198,290 @@ impl App {
         false
     }
 
-    fn handle_keyboard_event(&mut self, event: KeyEvent) -> anyhow::Result<()> {
-        match event {
-            KeyEvent {
-                key: Key::Escape, ..
-            } => self.window.close(),
-            KeyEvent {
-                key: Key::F11,
-                state: KeyState::Press,
-                ..
-            } => self.window.toggle_fullscreen(),
-            KeyEvent {
-                key: Key::Backspace,
-                state: KeyState::Press,
-                ..
-            } => {
-                self.command(Command::Delete)?;
-                // if self.text.len_chars() > 0 {
-                //     self.text.remove(self.text.len_chars() - 1..);
-                //     self.render.draw_frame(&self.text)?;
-                // }
-            }
-            KeyEvent {
-                key: Key::Return,
-                state: KeyState::Press,
-                ..
-            } => {
-                self.command(Command::NewLine)?;
-                // self.text.insert_char(self.text.len_chars(), '\n');
-                // self.render.draw_frame(&self.text)?;
-            }
-            KeyEvent {
-                translated: Some(c),
-                // key: Key::F11,
-                state: KeyState::Press,
+    fn handle_server_message(&mut self, msg: ServerMessage) {
+        log::trace!("client: received {:?}", msg);
+        match msg {
+            ServerMessage::Snapshot { text, selections } => {
+                self.text = ropey::Rope::from_str(&text);
+                self.selections = selections;
+                self.needs_redraw = true;
+            }
+            ServerMessage::Delta { range, text } => {
+                self.text.remove(range.clone());
+                self.text.insert(range.start, &text);
+                // Which match is selected is only known again after the next search
+                if let Some(search) = &mut self.search {
+                    search.current = None;
+                }
+                self.needs_redraw = true;
+            }
+            ServerMessage::Selections(selections) => {
+                self.selections = selections;
+                self.needs_redraw = true;
+            }
+            ServerMessage::Search(search) => {
+                let pattern = search.as_ref().map(|search| search.pattern.as_str());
+                if pattern != self.matcher.as_ref().map(Matcher::pattern) {
+                    // Likely not finished typing if it doesn't compile
+                    self.matcher = pattern.and_then(|pattern| Matcher::new(pattern).ok());
+                }
+                self.search = search;
+                self.needs_redraw = true;
+            }
+            ServerMessage::ReplacePreview(lines) => {
+                // Only the prompt that asked for it still wants it
+                if matches!(self.input.mode(), Mode::Replace { .. }) {
+                    if lines.is_empty() {
+                        self.status = "no matches".to_string();
+                    }
+                    self.replace_preview = lines;
+                    self.needs_redraw = true;
+                }
+            }
+            ServerMessage::Status(status) => {
+                log::info!("{}", status);
+                self.status = status;
+                self.needs_redraw = true;
+            }
+            ServerMessage::Error(error) => {
+                log::error!("{}", error);
+                self.status = error;
+                self.needs_redraw = true;
+            }
+            ServerMessage::Diagnostics(diagnostics) => self.diagnostics = diagnostics,
+            ServerMessage::History { revisions, current } => {
+                for (i, revision) in revisions.iter().enumerate() {
+                    let marker = if i == current { '>' } else { ' ' };
+                    log::info!(
+                        "{} {:>4} (from {:>4}, {:>5}s ago) {}",
+                        marker,
+                        i,
+                        revision.parent,
+                        revision.age.as_secs(),
+                        revision.description
+                    );
+                }
+            }
+            ServerMessage::FileChanged { path } => {
+                log::warn!("{} changed on disk with unsaved changes here", path);
+                // The status line shows the question, and input takes the answer
+                self.input.ask_external_change(path);
+                self.needs_redraw = true;
+            }
+            ServerMessage::Diff { path, diff } => {
+                log::info!("{} on disk -> buffer:\n{}", path, diff);
+            }
+            ServerMessage::Buffers { buffers, current } => {
+                for buffer in &buffers {
+                    log::info!(
+                        "{} {:>3} {}{}",
+                        if buffer.id == current { '>' } else { ' ' },
+                        buffer.id,
+                        buffer.name,
+                        if buffer.modified { " [+]" } else { "" }
+                    );
+                }
+                self.buffers = buffers;
+                self.current_buffer = Some(current);
+            }
+            ServerMessage::Files { root, files } => {
+                // Only wanted by whichever client has a picker open
+                if let Some(picker) = &mut self.picker {
+                    picker.set_files(PathBuf::from(root), files);
+                    self.needs_redraw = true;
+                }
+            }
+            ServerMessage::ConfirmQuit { unsaved } => {
+                self.confirming_quit = true;
+                let names: Vec<_> = unsaved.iter().map(|b| b.name.as_str()).collect();
+                self.status = format!("unsaved changes in {}, quit anyway?", names.join(", "));
+                log::warn!("{}", self.status);
+            }
+            ServerMessage::Quit => self.window.close(),
+        }
+    }
+
+    fn redraw_if_needed(&mut self) -> anyhow::Result<()> {
+        if std::mem::take(&mut self.needs_redraw) {
+            self.draw()?;
+        }
+        Ok(())
+    }
+
+    /// Draws a frame, scrolled to the primary cursor.
+    fn draw(&mut self) -> anyhow::Result<()> {
+        self.render
+            .scroll_to(&self.text, self.selections.primary().head);
+        let highlights = self.highlights();
+        self.render.draw_frame(
+            &self.text,
+            &self.selections,
+            &highlights,
+            self.overlay().as_ref(),
+            &self.status_line(),
+        )
+    }
+
+    fn status_line(&self) -> String {
+        match &self.search {
+            Some(SearchStatus {
+                current: Some(current),
+                total,
                 ..
-            } => {
-                self.command(Command::Insert(c))?;
-                // self.text.insert_char(self.text.len_chars(), c);
-                // self.render.draw_frame(&self.text)?;
-            }
-            // KeyEvent {
-            //     key: Key::A,
-            //     state: KeyState::Press,
-            //     ..
-            // } => {
-            //     self.render.draw_frame(&self.text)?;
-            // }
-            _ => {}
+            }) => format!(
+                "{} [{}/{}] {}",
+                self.input.mode(),
+                current + 1,
+                total,
+                self.status
+            ),
+            Some(SearchStatus { total: 0, .. }) => {
+                format!("{} [no matches] {}", self.input.mode(), self.status)
+            }
+            _ => format!("{} {}", self.input.mode(), self.status),
+        }
+    }
+
+    /// Matches of the search in the lines on screen.
+    fn highlights(&mut self) -> Vec<Range<usize>> {
+        let shown = self.render.shown_chars(&self.text);
+        match &mut self.matcher {
+            Some(matcher) => matcher
+                .matches(&self.text, shown)
+                .filter(|found| !found.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// A window over the top of the text, for the file picker or the replace preview.
+    fn overlay(&self) -> Option<Overlay> {
+        let lines = match &self.picker {
+            Some(picker) => self.picker_lines(picker),
+            None if !self.replace_preview.is_empty() => self.replace_preview_lines(),
+            None => return None,
         };
+        let columns = self.render.columns() as u32;
+        let width = (columns * 3 / 4).max(40.min(columns));
+        Some(Overlay {
+            x: (columns - width) / 2,
+            y: 1,
+            width,
+            lines,
+        })
+    }
 
-        Ok(())
+    /// The picker's query and how many files match it, then the best of them.
+    fn picker_lines(&self, picker: &Picker) -> Vec<OverlayLine> {
+        let height = PICKER_HEIGHT.min(self.render.text_lines().saturating_sub(3));
+
+        let (matched, total) = picker.counts();
+        let header = match picker.is_loading() {
+            true => format!("> {}  (listing files)", picker.query()),
+            false => format!("> {}  {}/{}", picker.query(), matched, total),
+        };
+        let mut lines = vec![OverlayLine {
+            text: header,
+            highlights: Vec::new(),
+            selected: false,
+        }];
+        lines.extend(picker.rows(height).map(|row| OverlayLine {
+            text: format!(" {}", row.text),
+            // Moved along by the space in front
+            highlights: row.positions.iter().map(|idx| idx + 1).collect(),
+            selected: row.selected,
+        }));
+        lines
+    }
+
+    /// How many lines the replacement changes, then the first of them before and after.
+    fn replace_preview_lines(&self) -> Vec<OverlayLine> {
+        let changed = self.replace_preview.len();
+        let count = PREVIEW_LINES.min(self.render.text_lines().saturating_sub(3) / 2);
+        let line = |text| OverlayLine {
+            text,
+            highlights: Vec::new(),
+            selected: false,
+        };
+        let mut lines = vec![line(format!(
+            "{} line{} change",
+            changed,
+            if changed == 1 { "" } else { "s" }
+        ))];
+        // Changes across lines show as far as the first line break
+        let first_line = |text: &str| text.lines().next().unwrap_or_default().to_string();
+        for replaced in self.replace_preview.iter().take(count) {
+            let before = first_line(&replaced.before);
+            lines.push(line(format!("{:>5} - {}", replaced.line + 1, before)));
+            lines.push(OverlayLine {
+                selected: true,
+                ..line(format!("{:>5} + {}", "", first_line(&replaced.after)))
+            });
+        }
+        lines
+    }
+
+    fn handle_keyboard_event(&mut self, event: KeyEvent) -> anyhow::Result<()> {
+        let mode = self.input.mode().clone();
+        let actions = self.input.handle(event);
+        self.handle_actions(actions, mode)
+    }
+
+    /// Carries out what input asked for, redrawing if it changed anything on screen or the mode
+    /// changed from `mode`.
+    fn handle_actions(&mut self, actions: Vec<Action>, mode: Mode) -> anyhow::Result<()> {
+        for action in actions {
+            match action {
+                // Return only opens results in the buffer listing them
+                Action::Command(Command::OpenResult) if !self.in_results() => {}
+                Action::Command(Command::Quit { .. }) if self.confirming_quit => {
+                    self.command(Command::Quit { force: true })?
+                }
+                Action::Command(command) => {
+                    self.confirming_quit = false;
+                    self.command(command)?
+                }
+                Action::ToggleFullscreen => self.window.toggle_fullscreen(),
+                Action::MovePicker(direction) => {
+                    if let Some(picker) = &mut self.picker {
+                        picker.move_selection(direction);
+                        self.needs_redraw = true;
+                    }
+                }
+                Action::Pick => {
+                    if let Some(path) = self.picker.as_ref().and_then(Picker::selected) {
+                        self.command(Command::Open {
+                            path: path.display().to_string(),
+                            position: None,
+                        })?
+                    }
+                }
+            }
+        }
+
+        // The picker is open for as long as input is in its mode
+        match self.input.mode() {
+            Mode::PickFile { query } => {
+                self.picker.get_or_insert_with(Picker::new).set_query(query)
+            }
+            _ => self.picker = None,
+        }
+        // The preview is only for the replacement being typed
+        if !matches!(self.input.mode(), Mode::Replace { .. }) {
+            self.replace_preview.clear();
+        }
+
+        if *self.input.mode() != mode {
+            self.needs_redraw = true;
+        }
+        self.redraw_if_needed()
+    }
+
+    /// Whether the current buffer lists what a project-wide search found.
+    fn in_results(&self) -> bool {
+        self.buffers
+            .iter()
+            .any(|buffer| Some(buffer.id) == self.current_buffer && buffer.results)
     }
 
     fn command(&self, command: Command) -> anyhow::Result<()> {
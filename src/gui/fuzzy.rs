@@ -0,0 +1,153 @@
+// Fuzzy matching for pickers, in the spirit of fzf: every char of the query has to appear in the
+// candidate in order, and of the ways they could line up the best scoring one is kept. Matches
+// score more for landing at the start of a path component or word and for following each other,
+// and lose a little for every char they skip over.
+//
+// Like searching, a query with no upper case letters ignores case.
+
+const SCORE_MATCH: i32 = 16;
+/// At the start of the candidate or just after a path separator.
+const BONUS_SEPARATOR: i32 = 9;
+/// After some other punctuation or whitespace, like the start of a word in `snake_case`.
+const BONUS_BOUNDARY: i32 = 8;
+/// An upper case letter after a lower case one, like the start of a word in `camelCase`.
+const BONUS_CAMEL: i32 = 7;
+/// Right after the previous char of the query.
+const BONUS_CONSECUTIVE: i32 = 4;
+/// Where the first char of the query matches counts for more.
+const FIRST_CHAR_MULTIPLIER: i32 = 2;
+const PENALTY_GAP_START: i32 = 3;
+const PENALTY_GAP_EXTENSION: i32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Higher is better.
+    pub score: i32,
+    /// Which chars of the candidate matched, in order.
+    pub positions: Vec<usize>,
+}
+
+/// How well `query` matches `candidate`, or `None` if the chars of `query` aren't all in
+/// `candidate` in order. An empty query matches anything.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<Match> {
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let fold = |c: char| match ignore_case {
+        true => c.to_lowercase().next().unwrap_or(c),
+        false => c,
+    };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some(Match {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+
+    // Most candidates don't match at all, which is cheap to find out before scoring
+    let mut rest = chars.iter().map(|&c| fold(c));
+    if !query.iter().all(|&q| rest.any(|c| c == q)) {
+        return None;
+    }
+
+    let n = chars.len();
+    let bonuses: Vec<i32> = (0..n)
+        .map(|j| bonus(j.checked_sub(1).map(|prev| chars[prev]), chars[j]))
+        .collect();
+
+    // best[j] is the best score for the query so far with its last char matched at j, and
+    // from[i * n + j] where char i - 1 of the query was matched to get that score at j
+    let mut best: Vec<Option<i32>> = (0..n)
+        .map(|j| {
+            (fold(chars[j]) == query[0]).then(|| SCORE_MATCH + bonuses[j] * FIRST_CHAR_MULTIPLIER)
+        })
+        .collect();
+    let mut from = vec![0; query.len() * n];
+    for (i, &q) in query.iter().enumerate().skip(1) {
+        let mut next = vec![None; n];
+        // The best of best[k] before j - 1, plus what the gap from there would cost if it
+        // started at 0, so it only needs adjusting by how far j is
+        let mut gapped: Option<(i32, usize)> = None;
+        for j in i..n {
+            if j >= 2 {
+                if let Some(score) = best[j - 2] {
+                    let score = score + PENALTY_GAP_EXTENSION * (j - 2) as i32;
+                    if gapped.is_none_or(|(best, _)| score > best) {
+                        gapped = Some((score, j - 2));
+                    }
+                }
+            }
+            if fold(chars[j]) != q {
+                continue;
+            }
+
+            let consecutive = best[j - 1].map(|score| (score + BONUS_CONSECUTIVE, j - 1));
+            let gap = gapped.map(|(score, k)| {
+                (
+                    score - PENALTY_GAP_EXTENSION * (j - 2) as i32 - PENALTY_GAP_START,
+                    k,
+                )
+            });
+            let (score, k) = match (consecutive, gap) {
+                (Some(consecutive), Some(gap)) if gap.0 > consecutive.0 => gap,
+                (Some(consecutive), _) => consecutive,
+                (None, Some(gap)) => gap,
+                (None, None) => continue,
+            };
+            next[j] = Some(score + SCORE_MATCH + bonuses[j]);
+            from[i * n + j] = k;
+        }
+        best = next;
+    }
+
+    let (end, score) = best
+        .iter()
+        .enumerate()
+        .filter_map(|(j, score)| score.map(|score| (j, score)))
+        // The earliest of equally good ends
+        .max_by_key(|&(j, score)| (score, std::cmp::Reverse(j)))?;
+    let mut positions = vec![end; query.len()];
+    for i in (1..query.len()).rev() {
+        positions[i - 1] = from[i * n + positions[i]];
+    }
+    Some(Match { score, positions })
+}
+
+/// What matching `c` is worth on top of `SCORE_MATCH`, given the char before it.
+fn bonus(prev: Option<char>, c: char) -> i32 {
+    match prev {
+        None | Some('/' | '\\') => BONUS_SEPARATOR,
+        Some(prev) if !prev.is_alphanumeric() && c.is_alphanumeric() => BONUS_BOUNDARY,
+        Some(prev) if prev.is_lowercase() && c.is_uppercase() => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+#[test]
+fn matches_in_order() {
+    let positions = |query, candidate| fuzzy_match(query, candidate).map(|m| m.positions);
+    assert_eq!(positions("fb", "foo/bar.rs"), Some(vec![0, 4]));
+    assert_eq!(positions("bf", "foo/bar.rs"), None);
+    assert_eq!(positions("fooo", "foo/bar.rs"), None);
+    assert_eq!(positions("", "foo/bar.rs"), Some(vec![]));
+
+    // The later r, at the start of a path component, beats the closer one inside a word
+    assert_eq!(
+        positions("edr", "src/editor/render.rs"),
+        Some(vec![4, 5, 11])
+    );
+    // and a run beats scattered chars
+    assert_eq!(positions("rs", "src/render.rs"), Some(vec![11, 12]));
+    assert_eq!(positions("BW", "BufferWriter"), Some(vec![0, 6]));
+
+    // Smart case
+    assert_eq!(positions("ed", "src/Editor.rs"), Some(vec![4, 5]));
+    assert_eq!(positions("Ed", "src/editor.rs"), None);
+}
+
+#[test]
+fn scores_rank_candidates() {
+    let score = |candidate| fuzzy_match("ed", candidate).unwrap().score;
+    assert!(score("src/editor.rs") > score("src/gui/render.rs"));
+    assert!(score("src/e_d.rs") > score("src/gui/render.rs"));
+}
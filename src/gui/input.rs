@@ -1,7 +1,9 @@
-use super::types::{Key, KeyEvent, Modifiers};
-use crate::Command;
+use super::keymap::{Binding, Chord, Keymap, Lookup, ModeKind};
+use super::types::{KeyEvent, KeyState};
+use crate::{Command, Direction, Motion, Resolution, Scope};
+use std::{iter, time::Instant};
 
-// Keymap
+// Default keymap (see keymap.json), by physical position so it stays put on any layout
 // `1234567890-=
 //  qwertyuiop[]
 //  asdfghjkl;'#
@@ -11,63 +13,927 @@ use crate::Command;
 // Movement dfjk
 // Word: ec
 // Paragraph: im
+// Select word, paragraph: w, a
+// Select inside brackets, quotes, again for outside: b, '
+// Select indented block, everything: t, ctrl+a
+// Add cursor below/above: v, space v
+// Split selections into lines: s
+// Select regex matches in selections: space s
+// Keep only the primary selection: x
 // Insert: l
 // Append: o
 // Change: .
 // Delete: p
 // Search: h, space h
 // Next result: n, space n
+// Replace regex matches: y, then the pattern, Return, the replacement ($1 for groups), Return
+// Search every file in the project: /
+// Open the result on the cursor's line of the results: Return
+// Open a file of the project by typing part of its path: q
 // Select line, grow selection up/down: line: r, space r
 // Begin line below/above: g, space g
-// Undo/redo: u
+// Undo/redo: u, space u
 // Repeat: ,
+// Quit: ctrl+q
+
+// Counts go before a key to do it that many times, up to 9999: 3j, 2u.
+// Change and delete are operators, applied to whatever the next key moves over or selects: pc
+// deletes to the next word, .a changes the paragraph, p3r deletes three lines. Pressing the
+// operator again applies it to the selection, so pp deletes what's selected. A count before an
+// operator multiplies one after it, and repeating a change repeats it whole, e.g. 2pc then ,
 
 // -- Insert --
 // Leave: Escape
 // Delete word backwards: ctrl+w
 
-enum Mode {
+// -- Search --
+// Search: Return
+// Cancel: Escape
+
+// -- Picker --
+// Next/previous: Down/Up, Tab/Shift+Tab
+// Open: Return
+// Cancel: Escape
+
+// -- File changed on disk --
+// Reload: r
+// Keep the buffer: k
+// Show the diff: d
+// Decide later: Escape
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
     Normal,
     Insert,
+    /// Typing a search pattern.
+    Search {
+        direction: Direction,
+        pattern: String,
+    },
+    /// Typing a regex to select the matches of in the selections.
+    SelectMatches {
+        pattern: String,
+    },
+    /// Typing a regex to search the project for.
+    Grep {
+        pattern: String,
+    },
+    /// Typing a regex to replace, then once it's submitted what to replace it with.
+    Replace {
+        pattern: String,
+        replacement: Option<String>,
+    },
+    /// Typing part of the path of a file to open.
+    PickFile {
+        query: String,
+    },
+    /// Asked what to do about a file that changed on disk underneath unsaved changes.
+    FileChanged {
+        path: String,
+    },
+}
+
+impl Mode {
+    fn kind(&self) -> ModeKind {
+        match self {
+            Self::Normal => ModeKind::Normal,
+            Self::Insert => ModeKind::Insert,
+            Self::Search { .. }
+            | Self::SelectMatches { .. }
+            | Self::Grep { .. }
+            | Self::Replace { .. } => ModeKind::Search,
+            Self::PickFile { .. } => ModeKind::Picker,
+            Self::FileChanged { .. } => ModeKind::FileChanged,
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => f.write_str("NORMAL"),
+            Self::Insert => f.write_str("INSERT"),
+            Self::Search {
+                direction: Direction::Forward,
+                pattern,
+            } => write!(f, "/{}", pattern),
+            Self::Search {
+                direction: Direction::Backward,
+                pattern,
+            } => write!(f, "?{}", pattern),
+            Self::SelectMatches { pattern } => write!(f, "select: {}", pattern),
+            Self::Grep { pattern } => write!(f, "grep: {}", pattern),
+            Self::Replace {
+                pattern,
+                replacement: None,
+            } => write!(f, "replace: {}", pattern),
+            Self::Replace {
+                pattern,
+                replacement: Some(replacement),
+            } => write!(f, "replace {} with: {}", pattern, replacement),
+            Self::PickFile { query } => write!(f, "open: {}", query),
+            Self::FileChanged { path } => write!(
+                f,
+                "{} changed on disk: reload (r), keep (k) or diff (d)?",
+                path
+            ),
+        }
+    }
+}
+
+/// Largest count, so a mistyped one can't ask for billions of commands.
+const MAX_COUNT: usize = 9999;
+
+/// A binding that waits for what it applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+}
+
+/// What a key press asks of the client.
+#[derive(Debug)]
+pub enum Action {
+    Command(Command),
+    ToggleFullscreen,
+    /// Moves the picker's selection down or up the list.
+    MovePicker(Direction),
+    /// Opens what's selected in the picker.
+    Pick,
 }
 
+/// Turns key presses into commands, through the keymap for the current mode.
 pub struct Input {
+    keymap: Keymap,
     mode: Mode,
+    /// Keys typed so far of a sequence that more keys could complete.
+    pending: Vec<Chord>,
+    /// When to stop waiting for the rest of `pending`.
+    deadline: Option<Instant>,
+    /// Typed in normal mode before a binding, to run it this many times.
+    count: Option<usize>,
+    /// An operator waiting for a motion or text object, and the count typed before it.
+    operator: Option<(Operator, usize)>,
+    /// Commands of the change being made, from the key that started it until back in normal
+    /// mode.
+    recording: Option<Vec<Command>>,
+    last_change: Vec<Command>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Input {
     pub fn new() -> Self {
-        Self { mode: Mode::Normal }
+        Self::with_keymap(Keymap::default())
+    }
+
+    pub fn with_keymap(keymap: Keymap) -> Self {
+        Self {
+            keymap,
+            mode: Mode::Normal,
+            pending: Vec::new(),
+            deadline: None,
+            count: None,
+            operator: None,
+            recording: None,
+            last_change: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    /// Asks what to do about `path` changing on disk, finishing whatever was being typed.
+    pub fn ask_external_change(&mut self, path: String) {
+        self.pending.clear();
+        self.deadline = None;
+        self.count = None;
+        self.operator = None;
+        if let Some(recording) = self.recording.take() {
+            self.last_change = recording;
+        }
+        self.mode = Mode::FileChanged { path };
     }
 
-    pub fn parse(&self, event: KeyEvent) -> Option<Command> {
+    /// When to call `timeout` if no other key is pressed first.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn handle(&mut self, event: KeyEvent) -> Vec<Action> {
+        if let KeyState::Release = event.state {
+            return Vec::new();
+        }
+        if event.scancode.is_modifier() {
+            return Vec::new();
+        }
+
+        self.pending.push(Chord {
+            scancode: event.scancode,
+            mods: event.mods,
+        });
+        match self.keymap.lookup(self.kind(), &self.pending) {
+            Lookup::Exact(binding) => {
+                self.pending.clear();
+                self.deadline = None;
+                self.run(binding)
+            }
+            Lookup::Prefix | Lookup::Ambiguous(_) => {
+                self.deadline = Some(Instant::now() + self.keymap.timeout);
+                Vec::new()
+            }
+            Lookup::None if self.pending.len() > 1 => {
+                // What came before doesn't lead anywhere with this key, so finish it and start
+                // again from this key
+                self.pending.pop();
+                let mut actions = self.timeout();
+                actions.extend(self.handle(event));
+                actions
+            }
+            Lookup::None => {
+                self.pending.clear();
+                self.deadline = None;
+                if self.mode == Mode::Normal && event.mods.is_empty() {
+                    if let Some(digit) = event.scancode.digit() {
+                        // A leading 0 isn't a count
+                        if digit > 0 || self.count.is_some() {
+                            let count = self.count.unwrap_or(0) * 10 + digit;
+                            self.count = Some(count.min(MAX_COUNT));
+                            return Vec::new();
+                        }
+                    }
+                }
+
+                self.count = None;
+                self.operator = None;
+                match event.translated {
+                    Some(c) => self.type_char(c),
+                    None => Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Stops waiting for the rest of a sequence, running what's been typed if it's bound.
+    pub fn timeout(&mut self) -> Vec<Action> {
+        let lookup = self.keymap.lookup(self.kind(), &self.pending);
+        self.pending.clear();
+        self.deadline = None;
+        match lookup {
+            Lookup::Exact(binding) | Lookup::Ambiguous(binding) => self.run(binding),
+            Lookup::Prefix | Lookup::None => Vec::new(),
+        }
+    }
+
+    /// Which bindings apply.
+    fn kind(&self) -> ModeKind {
+        match (&self.mode, self.operator) {
+            (Mode::Normal, Some(_)) => ModeKind::Operator,
+            (mode, _) => mode.kind(),
+        }
+    }
+
+    fn run(&mut self, binding: Binding) -> Vec<Action> {
+        let count = self.count.take().unwrap_or(1);
+        if let Some((operator, times)) = self.operator.take() {
+            return self.apply(operator, binding, (times * count).min(MAX_COUNT));
+        }
+
+        let commands = match binding {
+            Binding::MoveLeft
+            | Binding::MoveRight
+            | Binding::MoveUp
+            | Binding::MoveDown
+            | Binding::WordForward
+            | Binding::WordBackward
+            | Binding::LineStart
+            | Binding::LineEnd
+            | Binding::ParagraphForward
+            | Binding::ParagraphBackward
+            | Binding::DocumentStart
+            | Binding::DocumentEnd
+            | Binding::PageUp
+            | Binding::PageDown => vec![Command::Move(binding.motion().unwrap()); count],
+            Binding::SelectWord
+            | Binding::SelectParagraph
+            | Binding::SelectLine
+            | Binding::SelectBrackets
+            | Binding::SelectQuotes
+            | Binding::SelectIndent
+            | Binding::SelectAll => select(binding.scope().unwrap(), count),
+
+            Binding::Insert => self.begin_change(Vec::new(), Mode::Insert),
+            Binding::Append => self.begin_change(vec![Command::Move(Motion::Right)], Mode::Insert),
+            Binding::Change => {
+                self.operator = Some((Operator::Change, count));
+                Vec::new()
+            }
+            Binding::Delete => {
+                self.operator = Some((Operator::Delete, count));
+                Vec::new()
+            }
+            Binding::OpenLineBelow => {
+                self.begin_change(vec![Command::OpenLine(Direction::Forward)], Mode::Insert)
+            }
+            Binding::OpenLineAbove => {
+                self.begin_change(vec![Command::OpenLine(Direction::Backward)], Mode::Insert)
+            }
+            Binding::GrowLinesDown => vec![Command::GrowLines(Direction::Forward); count],
+            Binding::GrowLinesUp => vec![Command::GrowLines(Direction::Backward); count],
+            Binding::AddCursorBelow => vec![Command::AddCursor(Direction::Forward); count],
+            Binding::AddCursorAbove => vec![Command::AddCursor(Direction::Backward); count],
+            Binding::SplitLines => vec![Command::SplitLines],
+            Binding::SelectMatches => {
+                self.mode = Mode::SelectMatches {
+                    pattern: String::new(),
+                };
+                Vec::new()
+            }
+            Binding::KeepPrimary => vec![Command::KeepPrimary],
+            Binding::Search => self.begin_search(Direction::Forward),
+            Binding::SearchBackward => self.begin_search(Direction::Backward),
+            Binding::SearchNext => vec![Command::SearchNext(Direction::Forward); count],
+            Binding::SearchPrevious => vec![Command::SearchNext(Direction::Backward); count],
+            Binding::Grep => {
+                self.mode = Mode::Grep {
+                    pattern: String::new(),
+                };
+                Vec::new()
+            }
+            Binding::OpenResult => vec![Command::OpenResult],
+            Binding::PickFile => {
+                self.mode = Mode::PickFile {
+                    query: String::new(),
+                };
+                vec![Command::ListFiles]
+            }
+            Binding::Replace => {
+                self.mode = Mode::Replace {
+                    pattern: String::new(),
+                    replacement: None,
+                };
+                Vec::new()
+            }
+            Binding::Undo => vec![Command::Undo; count],
+            Binding::Redo => vec![Command::Redo; count],
+            Binding::Repeat => iter::repeat_n(&self.last_change, count)
+                .flatten()
+                .cloned()
+                .collect(),
+
+            Binding::NormalMode => match std::mem::replace(&mut self.mode, Mode::Normal) {
+                Mode::Search { .. } => vec![Command::CancelSearch],
+                _ => Vec::new(),
+            },
+            Binding::DeleteBackward => match self.prompt() {
+                Some(prompt) => {
+                    prompt.pop();
+                    self.preview()
+                }
+                None => vec![Command::Delete],
+            },
+            Binding::DeleteWordBackward => match self.prompt() {
+                Some(prompt) => {
+                    let word = prompt.trim_end().rfind(char::is_whitespace);
+                    prompt.truncate(word.map_or(0, |idx| idx + 1));
+                    self.preview()
+                }
+                None => vec![Command::DeleteWordBackward],
+            },
+            Binding::NewLine => vec![Command::NewLine],
+            Binding::Reload | Binding::KeepBuffer | Binding::ShowDiff => match &self.mode {
+                Mode::FileChanged { path } => {
+                    let path = path.clone();
+                    let resolution = binding.resolution().unwrap();
+                    // Still undecided after looking at the diff
+                    if resolution != Resolution::Diff {
+                        self.mode = Mode::Normal;
+                    }
+                    vec![Command::ResolveExternalChange { path, resolution }]
+                }
+                _ => Vec::new(),
+            },
+            Binding::NextItem => return vec![Action::MovePicker(Direction::Forward)],
+            Binding::PreviousItem => return vec![Action::MovePicker(Direction::Backward)],
+            Binding::SubmitSearch => match std::mem::replace(&mut self.mode, Mode::Normal) {
+                Mode::Search { direction, pattern } if !pattern.is_empty() => {
+                    vec![Command::Search { pattern, direction }]
+                }
+                Mode::SelectMatches { pattern } if !pattern.is_empty() => {
+                    vec![Command::SelectMatches(pattern)]
+                }
+                Mode::Grep { pattern } if !pattern.is_empty() => vec![Command::Grep(pattern)],
+                Mode::Replace {
+                    pattern,
+                    replacement: None,
+                } if !pattern.is_empty() => {
+                    self.mode = Mode::Replace {
+                        pattern,
+                        replacement: Some(String::new()),
+                    };
+                    self.preview()
+                }
+                Mode::Replace {
+                    pattern,
+                    replacement: Some(replacement),
+                } => vec![Command::Replace {
+                    pattern,
+                    replacement,
+                }],
+                Mode::Search { .. } => vec![Command::CancelSearch],
+                Mode::PickFile { .. } => return vec![Action::Pick],
+                Mode::SelectMatches { .. } | Mode::Grep { .. } | Mode::Replace { .. } => Vec::new(),
+                mode => {
+                    self.mode = mode;
+                    Vec::new()
+                }
+            },
+
+            Binding::Quit => vec![Command::Quit { force: false }],
+            Binding::ToggleFullscreen => return vec![Action::ToggleFullscreen],
+        };
+
+        self.emit(commands)
+    }
+
+    /// Applies `operator` to what `binding` moves over or selects, or to the selection if it's
+    /// the operator's own binding. Anything else cancels it.
+    fn apply(&mut self, operator: Operator, binding: Binding, count: usize) -> Vec<Action> {
+        let mut commands = match (operator, binding) {
+            (Operator::Delete, Binding::Delete) | (Operator::Change, Binding::Change) => Vec::new(),
+            _ => match (binding.motion(), binding.scope()) {
+                (Some(motion), _) => vec![Command::SelectTo(motion, count)],
+                (None, Some(scope)) => select(scope, count),
+                (None, None) => return Vec::new(),
+            },
+        };
+
+        commands.push(Command::DeleteSelection);
+        let mode = match operator {
+            Operator::Delete => Mode::Normal,
+            Operator::Change => Mode::Insert,
+        };
+        let commands = self.begin_change(commands, mode);
+        self.emit(commands)
+    }
+
+    /// What an unbound key that types `c` does.
+    fn type_char(&mut self, c: char) -> Vec<Action> {
+        if c.is_control() && c != '\t' {
+            return Vec::new();
+        }
         match self.mode {
-            Mode::Normal => self.parse_normal(event.key, event.mods),
-            Mode::Insert => self.parse_insert(event),
+            Mode::Normal => Vec::new(),
+            Mode::Insert => self.emit(vec![Command::Insert(c)]),
+            _ => {
+                if let Some(prompt) = self.prompt() {
+                    prompt.push(c);
+                }
+                let commands = self.preview();
+                self.emit(commands)
+            }
         }
     }
 
-    fn parse_normal(&self, key: Key, mods: Modifiers) -> Option<Command> {
-        match (key, mods) {
-            (Key::D, Modifiers::empty()) => Some(Command::MoveLeft),
-            (Key::F, Modifiers::empty()) => Some(Command::MoveRight),
-            (Key::J, Modifiers::empty()) => Some(Command::MoveDown),
-            (Key::K, Modifiers::empty()) => Some(Command::MoveUp),
-            (Key::L, Modifiers::empty()) => { self.mode = Mode::Insert; None },
-            _ => None
+    /// The text being typed into a prompt, if in one.
+    fn prompt(&mut self) -> Option<&mut String> {
+        match &mut self.mode {
+            Mode::Normal | Mode::Insert | Mode::FileChanged { .. } => None,
+            Mode::Search { pattern, .. }
+            | Mode::SelectMatches { pattern }
+            | Mode::Grep { pattern }
+            | Mode::Replace {
+                pattern,
+                replacement: None,
+            } => Some(pattern),
+            Mode::PickFile { query } => Some(query),
+            Mode::Replace {
+                replacement: Some(replacement),
+                ..
+            } => Some(replacement),
         }
     }
 
-    fn parse_insert(&self, event: KeyEvent) -> Option<Command> {
-        match event {
-            Event { key: Key::Escape, .. } => { self.mode = Mode::Normal; None }
-            Event { translated: Some(c), .. } => Some(Insert(c)),
-            _ => None
+    /// Shows what the prompt typed so far would do: where a search would go, or what a
+    /// replacement would change.
+    fn preview(&self) -> Vec<Command> {
+        match &self.mode {
+            Mode::Search { direction, pattern } => vec![Command::SearchPreview {
+                pattern: pattern.clone(),
+                direction: *direction,
+            }],
+            Mode::Replace {
+                pattern,
+                replacement: Some(replacement),
+            } => vec![Command::ReplacePreview {
+                pattern: pattern.clone(),
+                replacement: replacement.clone(),
+            }],
+            _ => Vec::new(),
         }
     }
+
+    /// Starts recording a change for repeating, switching to `mode`.
+    fn begin_change(&mut self, commands: Vec<Command>, mode: Mode) -> Vec<Command> {
+        self.recording = Some(Vec::new());
+        self.mode = mode;
+        commands
+    }
+
+    fn begin_search(&mut self, direction: Direction) -> Vec<Command> {
+        self.mode = Mode::Search {
+            direction,
+            pattern: String::new(),
+        };
+        Vec::new()
+    }
+
+    /// Records commands that are part of a change, finishing the change once back in normal
+    /// mode.
+    fn emit(&mut self, commands: Vec<Command>) -> Vec<Action> {
+        if let Some(recording) = &mut self.recording {
+            recording.extend(commands.iter().cloned());
+            if self.mode == Mode::Normal {
+                self.last_change = self.recording.take().unwrap();
+            }
+        }
+
+        commands.into_iter().map(Action::Command).collect()
+    }
+}
+
+/// Commands to select `count` of a text object. Only lines can be counted.
+fn select(scope: Scope, count: usize) -> Vec<Command> {
+    let grow = match scope {
+        Scope::Line => count - 1,
+        _ => 0,
+    };
+    iter::once(Command::Select(scope))
+        .chain(iter::repeat_n(Command::GrowLines(Direction::Forward), grow))
+        .collect()
+}
+
+#[cfg(test)]
+use super::types::{Modifiers, Scancode};
+
+#[cfg(test)]
+fn press(input: &mut Input, scancode: Scancode, translated: Option<char>) -> Vec<Command> {
+    // Bindings only look at the physical key
+    let event = KeyEvent {
+        state: KeyState::Press,
+        key: super::types::Key::Unknown,
+        scancode,
+        translated,
+        mods: Modifiers::empty(),
+        repeat: false,
+    };
+    input
+        .handle(event)
+        .into_iter()
+        .map(|action| match action {
+            Action::Command(command) => command,
+            action => panic!("expected a command, got {:?}", action),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn debug(commands: Vec<Command>) -> String {
+    format!("{:?}", commands)
+}
+
+#[test]
+fn normal_mode_keymap() {
+    let mut input = Input::new();
+    let mut keys = |key, translated| debug(press(&mut input, key, translated));
+
+    assert_eq!(keys(Scancode::D, Some('d')), "[Move(Left)]");
+    assert_eq!(keys(Scancode::F, Some('f')), "[Move(Right)]");
+    assert_eq!(keys(Scancode::J, Some('j')), "[Move(Down)]");
+    assert_eq!(keys(Scancode::K, Some('k')), "[Move(Up)]");
+    assert_eq!(keys(Scancode::E, Some('e')), "[Move(WordBackward)]");
+    assert_eq!(keys(Scancode::C, Some('c')), "[Move(WordForward)]");
+    assert_eq!(keys(Scancode::I, Some('i')), "[Move(ParagraphBackward)]");
+    assert_eq!(keys(Scancode::M, Some('m')), "[Move(ParagraphForward)]");
+    assert_eq!(keys(Scancode::N, Some('n')), "[SearchNext(Forward)]");
+    assert_eq!(keys(Scancode::R, Some('r')), "[GrowLines(Forward)]");
+    assert_eq!(keys(Scancode::P, Some('p')), "[]");
+    assert_eq!(keys(Scancode::P, Some('p')), "[DeleteSelection]");
+    assert_eq!(keys(Scancode::U, Some('u')), "[Undo]");
+
+    // Space reverses the next key only
+    assert_eq!(keys(Scancode::Space, Some(' ')), "[]");
+    assert_eq!(keys(Scancode::U, Some('u')), "[Redo]");
+    assert_eq!(keys(Scancode::Space, Some(' ')), "[]");
+    assert_eq!(keys(Scancode::N, Some('n')), "[SearchNext(Backward)]");
+    assert_eq!(keys(Scancode::Space, Some(' ')), "[]");
+    assert_eq!(keys(Scancode::R, Some('r')), "[GrowLines(Backward)]");
+    assert_eq!(keys(Scancode::R, Some('r')), "[GrowLines(Forward)]");
+}
+
+#[test]
+fn insert_and_repeat() {
+    let mut input = Input::new();
+
+    assert_eq!(debug(press(&mut input, Scancode::Space, Some(' '))), "[]");
+    assert_eq!(
+        debug(press(&mut input, Scancode::G, Some('g'))),
+        "[OpenLine(Backward)]"
+    );
+    assert_eq!(*input.mode(), Mode::Insert);
+    assert_eq!(
+        debug(press(&mut input, Scancode::H, Some('h'))),
+        "[Insert('h')]"
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::Backspace, Some('\x08'))),
+        "[Delete]"
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::I, Some('i'))),
+        "[Insert('i')]"
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::Escape, Some('\x1b'))),
+        "[]"
+    );
+    assert_eq!(*input.mode(), Mode::Normal);
+
+    // Moving around isn't part of the change
+    press(&mut input, Scancode::J, Some('j'));
+    assert_eq!(
+        debug(press(&mut input, Scancode::Comma, Some(','))),
+        "[OpenLine(Backward), Insert('h'), Delete, Insert('i')]"
+    );
+
+    press(&mut input, Scancode::Period, Some('.'));
+    assert_eq!(
+        debug(press(&mut input, Scancode::Period, Some('.'))),
+        "[DeleteSelection]"
+    );
+    press(&mut input, Scancode::X, Some('x'));
+    press(&mut input, Scancode::Escape, Some('\x1b'));
+    assert_eq!(
+        debug(press(&mut input, Scancode::Comma, Some(','))),
+        "[DeleteSelection, Insert('x')]"
+    );
+}
+
+#[test]
+fn search_prompt() {
+    let mut input = Input::new();
+    press(&mut input, Scancode::H, Some('h'));
+    press(&mut input, Scancode::A, Some('a'));
+    press(&mut input, Scancode::B, Some('b'));
+    press(&mut input, Scancode::Backspace, Some('\x08'));
+    assert_eq!(
+        debug(press(&mut input, Scancode::C, Some('c'))),
+        r#"[SearchPreview { pattern: "ac", direction: Forward }]"#
+    );
+    assert_eq!(input.mode().to_string(), "/ac");
+    assert_eq!(
+        debug(press(&mut input, Scancode::Enter, Some('\r'))),
+        r#"[Search { pattern: "ac", direction: Forward }]"#
+    );
+    assert_eq!(*input.mode(), Mode::Normal);
+
+    // Escape goes back to where the search started
+    press(&mut input, Scancode::Space, Some(' '));
+    press(&mut input, Scancode::H, Some('h'));
+    assert_eq!(
+        debug(press(&mut input, Scancode::A, Some('a'))),
+        r#"[SearchPreview { pattern: "a", direction: Backward }]"#
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::Escape, None)),
+        "[CancelSearch]"
+    );
+    assert_eq!(*input.mode(), Mode::Normal);
 }
 
-// Cursors on client or server?
+#[test]
+fn replace_prompt() {
+    let mut input = Input::new();
+    press(&mut input, Scancode::Y, Some('y'));
+    assert_eq!(debug(press(&mut input, Scancode::A, Some('a'))), "[]");
+    assert_eq!(
+        debug(press(&mut input, Scancode::Enter, Some('\r'))),
+        r#"[ReplacePreview { pattern: "a", replacement: "" }]"#
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::B, Some('b'))),
+        r#"[ReplacePreview { pattern: "a", replacement: "b" }]"#
+    );
+    assert_eq!(input.mode().to_string(), "replace a with: b");
+    assert_eq!(
+        debug(press(&mut input, Scancode::Enter, Some('\r'))),
+        r#"[Replace { pattern: "a", replacement: "b" }]"#
+    );
+    assert_eq!(*input.mode(), Mode::Normal);
+}
+
+#[test]
+fn picker_prompt() {
+    // Moving through the list isn't a command, so look at every action
+    fn keys(input: &mut Input, scancode: Scancode, translated: Option<char>) -> String {
+        let event = KeyEvent {
+            state: KeyState::Press,
+            key: super::types::Key::Unknown,
+            scancode,
+            translated,
+            mods: Modifiers::empty(),
+            repeat: false,
+        };
+        format!("{:?}", input.handle(event))
+    }
+
+    let mut input = Input::new();
+
+    assert_eq!(
+        keys(&mut input, Scancode::Q, Some('q')),
+        "[Command(ListFiles)]"
+    );
+    assert_eq!(keys(&mut input, Scancode::E, Some('e')), "[]");
+    assert_eq!(keys(&mut input, Scancode::D, Some('d')), "[]");
+    assert_eq!(
+        keys(&mut input, Scancode::Down, None),
+        "[MovePicker(Forward)]"
+    );
+    assert_eq!(
+        keys(&mut input, Scancode::Up, None),
+        "[MovePicker(Backward)]"
+    );
+    assert_eq!(keys(&mut input, Scancode::Backspace, Some('\x08')), "[]");
+    assert_eq!(
+        *input.mode(),
+        Mode::PickFile {
+            query: "e".to_string()
+        }
+    );
+    assert_eq!(keys(&mut input, Scancode::Enter, Some('\r')), "[Pick]");
+    assert_eq!(*input.mode(), Mode::Normal);
+
+    keys(&mut input, Scancode::Q, Some('q'));
+    assert_eq!(keys(&mut input, Scancode::Escape, None), "[]");
+    assert_eq!(*input.mode(), Mode::Normal);
+}
+
+#[test]
+fn file_changed_prompt() {
+    let mut input = Input::new();
+    press(&mut input, Scancode::L, Some('l'));
+    input.ask_external_change("a.txt".to_string());
+    assert_eq!(
+        input.mode().to_string(),
+        "a.txt changed on disk: reload (r), keep (k) or diff (d)?"
+    );
+    // Typing isn't part of the prompt
+    assert_eq!(debug(press(&mut input, Scancode::X, Some('x'))), "[]");
+    assert_eq!(
+        debug(press(&mut input, Scancode::D, Some('d'))),
+        r#"[ResolveExternalChange { path: "a.txt", resolution: Diff }]"#
+    );
+    assert_eq!(
+        debug(press(&mut input, Scancode::K, Some('k'))),
+        r#"[ResolveExternalChange { path: "a.txt", resolution: Keep }]"#
+    );
+    assert_eq!(*input.mode(), Mode::Normal);
+
+    input.ask_external_change("a.txt".to_string());
+    press(&mut input, Scancode::Escape, None);
+    assert_eq!(*input.mode(), Mode::Normal);
+}
 
-// enum InputCommand {
-// }
\ No newline at end of file
+#[test]
+fn bindings_follow_physical_keys() {
+    use super::keyboard::Layout;
+
+    for layout in [
+        Layout::QWERTY,
+        Layout::DVORAK,
+        Layout::AZERTY,
+        Layout::COLEMAK,
+    ] {
+        let mut input = Input::new();
+        let mut keys = |scancode| debug(press(&mut input, scancode, layout.translate(scancode)));
+        assert_eq!(keys(Scancode::D), "[Move(Left)]");
+        assert_eq!(keys(Scancode::K), "[Move(Up)]");
+        assert_eq!(keys(Scancode::Space), "[]");
+        assert_eq!(keys(Scancode::N), "[SearchNext(Backward)]");
+
+        // Text still comes from the layout
+        assert_eq!(keys(Scancode::L), "[]");
+        let typed = format!("[Insert({:?})]", layout.translate(Scancode::Q).unwrap());
+        assert_eq!(keys(Scancode::Q), typed);
+    }
+
+    assert_eq!(Layout::DVORAK.translate(Scancode::D), Some('e'));
+    assert_eq!(Layout::AZERTY.translate(Scancode::Q), Some('a'));
+    assert_eq!(Layout::COLEMAK.translate(Scancode::K), Some('e'));
+}
+
+#[test]
+fn ambiguous_sequences_wait() {
+    let mut keymap = Keymap::default();
+    keymap
+        .apply(r#"{ "normal": { "g g": "document_start" } }"#)
+        .unwrap();
+    let mut input = Input::with_keymap(keymap);
+
+    assert_eq!(debug(press(&mut input, Scancode::G, Some('g'))), "[]");
+    assert!(input.deadline().is_some());
+    assert_eq!(
+        debug(press(&mut input, Scancode::G, Some('g'))),
+        "[Move(DocumentStart)]"
+    );
+
+    // Timing out runs the shorter binding
+    press(&mut input, Scancode::G, Some('g'));
+    let actions = input.timeout();
+    assert!(matches!(
+        &actions[..],
+        [Action::Command(Command::OpenLine(Direction::Forward))]
+    ));
+    assert_eq!(*input.mode(), Mode::Insert);
+    assert!(input.deadline().is_none());
+
+    // A key that doesn't continue the sequence finishes it and then counts by itself
+    let mut input = Input::new();
+    press(&mut input, Scancode::Space, Some(' '));
+    assert_eq!(
+        debug(press(&mut input, Scancode::D, Some('d'))),
+        "[Move(Left)]"
+    );
+}
+
+#[test]
+fn counts_and_operators() {
+    let mut input = Input::new();
+    let mut keys = |scancodes: &[Scancode]| {
+        let commands = scancodes
+            .iter()
+            .flat_map(|&scancode| press(&mut input, scancode, None))
+            .collect();
+        debug(commands)
+    };
+
+    assert_eq!(
+        keys(&[Scancode::Digit3, Scancode::J]),
+        "[Move(Down), Move(Down), Move(Down)]"
+    );
+    assert_eq!(keys(&[Scancode::Digit0, Scancode::U]), "[Undo]");
+    assert_eq!(
+        keys(&[Scancode::P, Scancode::C]),
+        "[SelectTo(WordForward, 1), DeleteSelection]"
+    );
+    assert_eq!(
+        keys(&[Scancode::Digit2, Scancode::P, Scancode::Digit3, Scancode::C]),
+        "[SelectTo(WordForward, 6), DeleteSelection]"
+    );
+    assert_eq!(
+        keys(&[Scancode::P, Scancode::Digit1, Scancode::Digit2, Scancode::R]),
+        format!(
+            "[Select(Line), {}DeleteSelection]",
+            "GrowLines(Forward), ".repeat(11)
+        )
+    );
+
+    // Counts stop growing at the most there can be, multiplied or not
+    assert_eq!(keys(&[Scancode::Digit9; 30]), "[]");
+    assert_eq!(
+        keys(&[Scancode::P, Scancode::Digit2, Scancode::C]),
+        format!("[SelectTo(WordForward, {}), DeleteSelection]", MAX_COUNT)
+    );
+
+    // Escape cancels an operator and a count
+    assert_eq!(
+        keys(&[Scancode::Digit2, Scancode::P, Scancode::Escape]),
+        "[]"
+    );
+    assert_eq!(keys(&[Scancode::D]), "[Move(Left)]");
+
+    // Changing a paragraph, then repeating the whole change twice
+    assert_eq!(
+        keys(&[Scancode::Period, Scancode::A]),
+        "[Select(Paragraph), DeleteSelection]"
+    );
+    assert_eq!(*input.mode(), Mode::Insert);
+    let typed = press(&mut input, Scancode::X, Some('x'));
+    assert_eq!(debug(typed), "[Insert('x')]");
+    press(&mut input, Scancode::Escape, None);
+    assert_eq!(
+        debug(
+            [Scancode::Digit2, Scancode::Comma]
+                .iter()
+                .flat_map(|&scancode| press(&mut input, scancode, None))
+                .collect()
+        ),
+        "[Select(Paragraph), DeleteSelection, Insert('x'), \
+         Select(Paragraph), DeleteSelection, Insert('x')]"
+    );
+}
@@ -1 +1,402 @@
+// Physical key positions, so shortcuts stay on the same keys whatever the keyboard layout, the
+// way games do it. Text input still goes through the layout, see `KeyEvent::translated`.
 
+/// A key by where it is on the keyboard, named after what it's labelled on a US QWERTY layout.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Scancode {
+    Escape,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Digit0,
+    Minus,
+    Equal,
+    Backspace,
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Enter,
+    LeftControl,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Apostrophe,
+    Backtick,
+    LeftShift,
+    /// Next to Enter; `#` on ISO keyboards.
+    Backslash,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+    LeftAlt,
+    Space,
+    CapsLock,
+    /// The extra key left of Z on ISO keyboards.
+    IntlBackslash,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    RightControl,
+    RightAlt,
+    LeftMeta,
+    RightMeta,
+    Menu,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Up,
+    Down,
+
+    Unknown,
+}
+
+impl Scancode {
+    /// From a PC scan code set 1 make code, with 0x100 set for keys sent with the 0xE0 prefix.
+    /// That's bits 16-24 of a Win32 key message's lparam.
+    pub fn from_set1(code: u16) -> Self {
+        match code {
+            0x01 => Self::Escape,
+            0x02 => Self::Digit1,
+            0x03 => Self::Digit2,
+            0x04 => Self::Digit3,
+            0x05 => Self::Digit4,
+            0x06 => Self::Digit5,
+            0x07 => Self::Digit6,
+            0x08 => Self::Digit7,
+            0x09 => Self::Digit8,
+            0x0a => Self::Digit9,
+            0x0b => Self::Digit0,
+            0x0c => Self::Minus,
+            0x0d => Self::Equal,
+            0x0e => Self::Backspace,
+            0x0f => Self::Tab,
+            0x10 => Self::Q,
+            0x11 => Self::W,
+            0x12 => Self::E,
+            0x13 => Self::R,
+            0x14 => Self::T,
+            0x15 => Self::Y,
+            0x16 => Self::U,
+            0x17 => Self::I,
+            0x18 => Self::O,
+            0x19 => Self::P,
+            0x1a => Self::LeftBracket,
+            0x1b => Self::RightBracket,
+            0x1c | 0x11c => Self::Enter,
+            0x1d => Self::LeftControl,
+            0x1e => Self::A,
+            0x1f => Self::S,
+            0x20 => Self::D,
+            0x21 => Self::F,
+            0x22 => Self::G,
+            0x23 => Self::H,
+            0x24 => Self::J,
+            0x25 => Self::K,
+            0x26 => Self::L,
+            0x27 => Self::Semicolon,
+            0x28 => Self::Apostrophe,
+            0x29 => Self::Backtick,
+            0x2a => Self::LeftShift,
+            0x2b => Self::Backslash,
+            0x2c => Self::Z,
+            0x2d => Self::X,
+            0x2e => Self::C,
+            0x2f => Self::V,
+            0x30 => Self::B,
+            0x31 => Self::N,
+            0x32 => Self::M,
+            0x33 => Self::Comma,
+            0x34 => Self::Period,
+            0x35 => Self::Slash,
+            0x36 => Self::RightShift,
+            0x38 => Self::LeftAlt,
+            0x39 => Self::Space,
+            0x3a => Self::CapsLock,
+            0x3b => Self::F1,
+            0x3c => Self::F2,
+            0x3d => Self::F3,
+            0x3e => Self::F4,
+            0x3f => Self::F5,
+            0x40 => Self::F6,
+            0x41 => Self::F7,
+            0x42 => Self::F8,
+            0x43 => Self::F9,
+            0x44 => Self::F10,
+            0x56 => Self::IntlBackslash,
+            0x57 => Self::F11,
+            0x58 => Self::F12,
+            0x11d => Self::RightControl,
+            0x138 => Self::RightAlt,
+            0x147 => Self::Home,
+            0x148 => Self::Up,
+            0x149 => Self::PageUp,
+            0x14b => Self::Left,
+            0x14d => Self::Right,
+            0x14f => Self::End,
+            0x150 => Self::Down,
+            0x151 => Self::PageDown,
+            0x152 => Self::Insert,
+            0x153 => Self::Delete,
+            0x15b => Self::LeftMeta,
+            0x15c => Self::RightMeta,
+            0x15d => Self::Menu,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// From a Linux evdev key code, which is what Wayland sends and X11 sends plus 8. The main
+    /// block of keys has the same codes as set 1; the rest are numbered on from there.
+    pub fn from_evdev(code: u32) -> Self {
+        match code {
+            0x01..=0x58 => Self::from_set1(code as u16),
+            96 => Self::Enter,
+            97 => Self::RightControl,
+            100 => Self::RightAlt,
+            102 => Self::Home,
+            103 => Self::Up,
+            104 => Self::PageUp,
+            105 => Self::Left,
+            106 => Self::Right,
+            107 => Self::End,
+            108 => Self::Down,
+            109 => Self::PageDown,
+            110 => Self::Insert,
+            111 => Self::Delete,
+            125 => Self::LeftMeta,
+            126 => Self::RightMeta,
+            127 => Self::Menu,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Parses a key name as used in keymaps: the US QWERTY label in lower case, or a name for
+    /// keys without one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "escape" => Self::Escape,
+            "1" => Self::Digit1,
+            "2" => Self::Digit2,
+            "3" => Self::Digit3,
+            "4" => Self::Digit4,
+            "5" => Self::Digit5,
+            "6" => Self::Digit6,
+            "7" => Self::Digit7,
+            "8" => Self::Digit8,
+            "9" => Self::Digit9,
+            "0" => Self::Digit0,
+            "minus" => Self::Minus,
+            "equal" => Self::Equal,
+            "backspace" => Self::Backspace,
+            "tab" => Self::Tab,
+            "q" => Self::Q,
+            "w" => Self::W,
+            "e" => Self::E,
+            "r" => Self::R,
+            "t" => Self::T,
+            "y" => Self::Y,
+            "u" => Self::U,
+            "i" => Self::I,
+            "o" => Self::O,
+            "p" => Self::P,
+            "left_bracket" => Self::LeftBracket,
+            "right_bracket" => Self::RightBracket,
+            "enter" => Self::Enter,
+            "a" => Self::A,
+            "s" => Self::S,
+            "d" => Self::D,
+            "f" => Self::F,
+            "g" => Self::G,
+            "h" => Self::H,
+            "j" => Self::J,
+            "k" => Self::K,
+            "l" => Self::L,
+            "semicolon" => Self::Semicolon,
+            "apostrophe" => Self::Apostrophe,
+            "backtick" => Self::Backtick,
+            "backslash" => Self::Backslash,
+            "z" => Self::Z,
+            "x" => Self::X,
+            "c" => Self::C,
+            "v" => Self::V,
+            "b" => Self::B,
+            "n" => Self::N,
+            "m" => Self::M,
+            "comma" => Self::Comma,
+            "period" => Self::Period,
+            "slash" => Self::Slash,
+            "space" => Self::Space,
+            "intl_backslash" => Self::IntlBackslash,
+            "f1" => Self::F1,
+            "f2" => Self::F2,
+            "f3" => Self::F3,
+            "f4" => Self::F4,
+            "f5" => Self::F5,
+            "f6" => Self::F6,
+            "f7" => Self::F7,
+            "f8" => Self::F8,
+            "f9" => Self::F9,
+            "f10" => Self::F10,
+            "f11" => Self::F11,
+            "f12" => Self::F12,
+            "menu" => Self::Menu,
+            "insert" => Self::Insert,
+            "delete" => Self::Delete,
+            "home" => Self::Home,
+            "end" => Self::End,
+            "page_up" => Self::PageUp,
+            "page_down" => Self::PageDown,
+            "left" => Self::Left,
+            "right" => Self::Right,
+            "up" => Self::Up,
+            "down" => Self::Down,
+            _ => return None,
+        })
+    }
+
+    /// The number on a key in the row above the letters.
+    pub fn digit(self) -> Option<usize> {
+        const DIGITS: [Scancode; 10] = [
+            Scancode::Digit0,
+            Scancode::Digit1,
+            Scancode::Digit2,
+            Scancode::Digit3,
+            Scancode::Digit4,
+            Scancode::Digit5,
+            Scancode::Digit6,
+            Scancode::Digit7,
+            Scancode::Digit8,
+            Scancode::Digit9,
+        ];
+        DIGITS.iter().position(|&digit| digit == self)
+    }
+
+    /// Modifier keys, which only matter in combination with others.
+    pub fn is_modifier(self) -> bool {
+        matches!(
+            self,
+            Self::LeftShift
+                | Self::RightShift
+                | Self::LeftControl
+                | Self::RightControl
+                | Self::LeftAlt
+                | Self::RightAlt
+                | Self::LeftMeta
+                | Self::RightMeta
+        )
+    }
+}
+
+/// The character keys of a layout, for tests: what the three letter rows type, from the key
+/// labelled Q, A and Z on US QWERTY.
+#[cfg(test)]
+pub struct Layout {
+    rows: [&'static str; 3],
+}
+
+#[cfg(test)]
+impl Layout {
+    pub const QWERTY: Self = Self {
+        rows: ["qwertyuiop[]", "asdfghjkl;'#", "zxcvbnm,./"],
+    };
+    pub const DVORAK: Self = Self {
+        rows: ["',.pyfgcrl/=", "aoeuidhtns-#", ";qjkxbmwvz"],
+    };
+    pub const AZERTY: Self = Self {
+        rows: ["azertyuiop^$", "qsdfghjklmù*", "wxcvbn,;:!"],
+    };
+    pub const COLEMAK: Self = Self {
+        rows: ["qwfpgjluy;[]", "arstdhneio'#", "zxcvbkm,./"],
+    };
+
+    /// What the key at `scancode` types on this layout.
+    pub fn translate(&self, scancode: Scancode) -> Option<char> {
+        use Scancode::*;
+        const ROWS: [&[Scancode]; 3] = [
+            &[Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket],
+            &[A, S, D, F, G, H, J, K, L, Semicolon, Apostrophe, Backslash],
+            &[Z, X, C, V, B, N, M, Comma, Period, Slash],
+        ];
+        match scancode {
+            Space => Some(' '),
+            _ => ROWS.iter().zip(self.rows).find_map(|(keys, chars)| {
+                let column = keys.iter().position(|&key| key == scancode)?;
+                chars.chars().nth(column)
+            }),
+        }
+    }
+}
+
+#[test]
+fn evdev_codes() {
+    // Win32 and Linux agree on where keys are
+    assert_eq!(Scancode::from_evdev(30), Scancode::from_set1(0x1e));
+    assert_eq!(Scancode::from_evdev(30), Scancode::A);
+    assert_eq!(Scancode::from_evdev(86), Scancode::IntlBackslash);
+    for (set1, evdev) in [
+        (0x11c, 96),
+        (0x11d, 97),
+        (0x138, 100),
+        (0x147, 102),
+        (0x148, 103),
+        (0x149, 104),
+        (0x14b, 105),
+        (0x14d, 106),
+        (0x14f, 107),
+        (0x150, 108),
+        (0x151, 109),
+        (0x152, 110),
+        (0x153, 111),
+        (0x15b, 125),
+        (0x15c, 126),
+        (0x15d, 127),
+    ] {
+        assert_eq!(Scancode::from_set1(set1), Scancode::from_evdev(evdev));
+    }
+    assert_eq!(Scancode::from_evdev(240), Scancode::Unknown);
+}
@@ -0,0 +1,406 @@
+use super::types::{Modifiers, Scancode};
+use crate::{Motion, Resolution, Scope};
+use anyhow::Context as _;
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Bindings from key sequences to actions, per mode. The defaults are in keymap.json; a user's
+// keymap.json in the config directory is layered on top, where binding a sequence to `null`
+// removes it.
+//
+// A sequence is keys separated by spaces, each key optionally preceded by modifiers:
+// "d", "space h", "ctrl+w", "ctrl+shift+f11". Keys are named by physical position on a US
+// QWERTY keyboard (see `Scancode`), so "d" is the same key whatever the layout.
+
+const DEFAULT: &str = include_str!("keymap.json");
+
+/// A key pressed while holding some modifiers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub scancode: Scancode,
+    pub mods: Modifiers,
+}
+
+impl std::str::FromStr for Chord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut mods = Modifiers::empty();
+        let mut parts = s.split('+').peekable();
+        let key = loop {
+            let part = parts.next().unwrap();
+            if parts.peek().is_none() {
+                break part;
+            }
+            mods |= match part {
+                "ctrl" => Modifiers::CONTROL,
+                "alt" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                _ => anyhow::bail!("unknown modifier `{}` in `{}`", part, s),
+            };
+        };
+
+        match Scancode::from_name(key) {
+            Some(scancode) => Ok(Self { scancode, mods }),
+            None => anyhow::bail!("unknown key `{}`", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sequence(pub Vec<Chord>);
+
+impl std::str::FromStr for Sequence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let chords = s
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        match chords.is_empty() {
+            true => anyhow::bail!("empty key sequence"),
+            false => Ok(Self(chords)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Sequence {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Something a key sequence can be bound to. What some of these do depends on the mode, e.g.
+/// `delete_backward` deletes from the document in insert mode but from the pattern in search mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binding {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    WordForward,
+    WordBackward,
+    LineStart,
+    LineEnd,
+    ParagraphForward,
+    ParagraphBackward,
+    DocumentStart,
+    DocumentEnd,
+    PageUp,
+    PageDown,
+    SelectWord,
+    SelectParagraph,
+    SelectLine,
+    SelectBrackets,
+    SelectQuotes,
+    SelectIndent,
+    SelectAll,
+
+    Insert,
+    Append,
+    Change,
+    Delete,
+    OpenLineBelow,
+    OpenLineAbove,
+    GrowLinesDown,
+    GrowLinesUp,
+    AddCursorBelow,
+    AddCursorAbove,
+    SplitLines,
+    SelectMatches,
+    KeepPrimary,
+    Search,
+    SearchBackward,
+    SearchNext,
+    SearchPrevious,
+    Replace,
+    Grep,
+    OpenResult,
+    PickFile,
+    Undo,
+    Redo,
+    Repeat,
+
+    NormalMode,
+    DeleteBackward,
+    DeleteWordBackward,
+    NewLine,
+    SubmitSearch,
+    NextItem,
+    PreviousItem,
+    Reload,
+    KeepBuffer,
+    ShowDiff,
+
+    Quit,
+    ToggleFullscreen,
+}
+
+impl Binding {
+    /// Where a binding that moves the cursor moves it.
+    pub fn motion(self) -> Option<Motion> {
+        Some(match self {
+            Self::MoveLeft => Motion::Left,
+            Self::MoveRight => Motion::Right,
+            Self::MoveUp => Motion::Up,
+            Self::MoveDown => Motion::Down,
+            Self::WordForward => Motion::WordForward,
+            Self::WordBackward => Motion::WordBackward,
+            Self::LineStart => Motion::LineStart,
+            Self::LineEnd => Motion::LineEnd,
+            Self::ParagraphForward => Motion::ParagraphForward,
+            Self::ParagraphBackward => Motion::ParagraphBackward,
+            Self::DocumentStart => Motion::DocumentStart,
+            Self::DocumentEnd => Motion::DocumentEnd,
+            Self::PageUp => Motion::PageUp,
+            Self::PageDown => Motion::PageDown,
+            _ => return None,
+        })
+    }
+
+    /// What a binding that selects a text object selects.
+    pub fn scope(self) -> Option<Scope> {
+        match self {
+            Self::SelectWord => Some(Scope::Word),
+            Self::SelectParagraph => Some(Scope::Paragraph),
+            Self::SelectLine => Some(Scope::Line),
+            Self::SelectBrackets => Some(Scope::Brackets),
+            Self::SelectQuotes => Some(Scope::Quotes),
+            Self::SelectIndent => Some(Scope::Indent),
+            Self::SelectAll => Some(Scope::Buffer),
+            _ => None,
+        }
+    }
+
+    /// How a binding that answers a file changing on disk answers.
+    pub fn resolution(self) -> Option<Resolution> {
+        match self {
+            Self::Reload => Some(Resolution::Reload),
+            Self::KeepBuffer => Some(Resolution::Keep),
+            Self::ShowDiff => Some(Resolution::Diff),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ModeKind {
+    Normal,
+    /// Normal mode after an operator, waiting for what it applies to. Falls back to the normal
+    /// mode bindings.
+    Operator,
+    Insert,
+    Search,
+    /// Choosing from a list while typing. Falls back to the search mode bindings.
+    Picker,
+    /// Choosing what to do about a file that changed on disk.
+    FileChanged,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    /// Milliseconds to wait for the rest of a sequence when what's been typed is already bound.
+    timeout: Option<u64>,
+    #[serde(default)]
+    normal: HashMap<Sequence, Option<Binding>>,
+    #[serde(default)]
+    operator: HashMap<Sequence, Option<Binding>>,
+    #[serde(default)]
+    insert: HashMap<Sequence, Option<Binding>>,
+    #[serde(default)]
+    search: HashMap<Sequence, Option<Binding>>,
+    #[serde(default)]
+    picker: HashMap<Sequence, Option<Binding>>,
+    #[serde(default)]
+    file_changed: HashMap<Sequence, Option<Binding>>,
+}
+
+/// What typing a sequence so far amounts to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Not bound, and no binding starts with it.
+    None,
+    /// Only the start of longer sequences.
+    Prefix,
+    /// Bound, and nothing longer starts with it.
+    Exact(Binding),
+    /// Bound, but also the start of longer sequences.
+    Ambiguous(Binding),
+}
+
+pub struct Keymap {
+    modes: HashMap<ModeKind, HashMap<Sequence, Binding>>,
+    pub timeout: Duration,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self {
+            modes: HashMap::new(),
+            timeout: Duration::ZERO,
+        };
+        keymap.apply(DEFAULT).expect("default keymap is invalid");
+        keymap
+    }
+}
+
+impl Keymap {
+    /// The default keymap with the user's keymap file, if there is one, on top.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut keymap = Self::default();
+        if let Some(path) = user_path().filter(|path| path.exists()) {
+            keymap.apply_file(&path)?;
+        }
+        Ok(keymap)
+    }
+
+    pub fn apply_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        self.apply(&json)
+            .with_context(|| format!("invalid keymap {}", path.display()))
+    }
+
+    /// Adds the bindings in `json` to this keymap, replacing any for the same sequences.
+    pub fn apply(&mut self, json: &str) -> anyhow::Result<()> {
+        let file: File = serde_json::from_str(json)?;
+        if let Some(timeout) = file.timeout {
+            self.timeout = Duration::from_millis(timeout);
+        }
+
+        for (mode, bindings) in [
+            (ModeKind::Normal, file.normal),
+            (ModeKind::Operator, file.operator),
+            (ModeKind::Insert, file.insert),
+            (ModeKind::Search, file.search),
+            (ModeKind::Picker, file.picker),
+            (ModeKind::FileChanged, file.file_changed),
+        ] {
+            let mode = self.modes.entry(mode).or_default();
+            for (sequence, binding) in bindings {
+                match binding {
+                    Some(binding) => mode.insert(sequence, binding),
+                    None => mode.remove(&sequence),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    pub fn lookup(&self, mode: ModeKind, keys: &[Chord]) -> Lookup {
+        let layers = match mode {
+            ModeKind::Operator => &[ModeKind::Operator, ModeKind::Normal][..],
+            ModeKind::Picker => &[ModeKind::Picker, ModeKind::Search][..],
+            _ => std::slice::from_ref(&mode),
+        };
+
+        let mut exact = None;
+        let mut prefix = false;
+        for bindings in layers.iter().filter_map(|mode| self.modes.get(mode)) {
+            for (sequence, &binding) in bindings {
+                if sequence.0 == keys {
+                    // Earlier layers take precedence
+                    exact = exact.or(Some(binding));
+                } else if sequence.0.starts_with(keys) {
+                    prefix = true;
+                }
+            }
+        }
+
+        match (exact, prefix) {
+            (None, false) => Lookup::None,
+            (None, true) => Lookup::Prefix,
+            (Some(binding), false) => Lookup::Exact(binding),
+            (Some(binding), true) => Lookup::Ambiguous(binding),
+        }
+    }
+}
+
+/// `keymap.json` in the platform's config directory.
+fn user_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(dir.join("kavi").join("keymap.json"))
+}
+
+#[test]
+fn default_keymap() {
+    let keymap = Keymap::default();
+    let keys = |s: &str| s.parse::<Sequence>().unwrap().0;
+    assert_eq!(
+        keymap.lookup(ModeKind::Normal, &keys("d")),
+        Lookup::Exact(Binding::MoveLeft)
+    );
+    assert_eq!(
+        keymap.lookup(ModeKind::Normal, &keys("space")),
+        Lookup::Prefix
+    );
+    assert_eq!(
+        keymap.lookup(ModeKind::Insert, &keys("ctrl+w")),
+        Lookup::Exact(Binding::DeleteWordBackward)
+    );
+    assert_eq!(keymap.lookup(ModeKind::Insert, &keys("d")), Lookup::None);
+
+    // After an operator, text objects and then normal mode
+    assert_eq!(
+        keymap.lookup(ModeKind::Operator, &keys("r")),
+        Lookup::Exact(Binding::SelectLine)
+    );
+    assert_eq!(
+        keymap.lookup(ModeKind::Operator, &keys("c")),
+        Lookup::Exact(Binding::WordForward)
+    );
+
+    // Pickers edit the query like any prompt
+    assert_eq!(
+        keymap.lookup(ModeKind::Picker, &keys("down")),
+        Lookup::Exact(Binding::NextItem)
+    );
+    assert_eq!(
+        keymap.lookup(ModeKind::Picker, &keys("backspace")),
+        Lookup::Exact(Binding::DeleteBackward)
+    );
+}
+
+#[test]
+fn user_keymap_overrides() {
+    let mut keymap = Keymap::default();
+    keymap
+        .apply(r#"{ "timeout": 250, "normal": { "d": null, "g g": "document_start" } }"#)
+        .unwrap();
+    let keys = |s: &str| s.parse::<Sequence>().unwrap().0;
+    assert_eq!(keymap.timeout, Duration::from_millis(250));
+    assert_eq!(keymap.lookup(ModeKind::Normal, &keys("d")), Lookup::None);
+    assert_eq!(
+        keymap.lookup(ModeKind::Normal, &keys("g")),
+        Lookup::Ambiguous(Binding::OpenLineBelow)
+    );
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let error = |json: &str| format!("{:#}", Keymap::default().apply(json).unwrap_err());
+
+    let bad_key = "{\n    \"normal\": {\n        \"ctrl+dd\": \"undo\"\n    }\n}";
+    assert_eq!(error(bad_key), "unknown key `dd` at line 3 column 17");
+
+    let bad_binding = "{\n    \"insert\": {\n        \"d\": \"dance\"\n    }\n}";
+    assert!(error(bad_binding).starts_with("unknown variant `dance`"));
+    assert!(error(bad_binding).ends_with("at line 3 column 20"));
+
+    assert!(error("{ \"visual\": {} }").starts_with("unknown field `visual`"));
+}
@@ -0,0 +1,171 @@
+use super::fuzzy::{fuzzy_match, Match};
+use crate::Direction;
+use std::path::{Path, PathBuf};
+
+/// Picking a file of the project by typing part of its path. The files come from the server,
+/// and are ranked on the client as the query changes.
+#[derive(Debug, Default)]
+pub struct Picker {
+    /// Where the files are, as the server sees it. `None` until they arrive.
+    root: Option<PathBuf>,
+    /// Paths relative to `root`.
+    files: Vec<String>,
+    query: String,
+    /// The files matching the query, best first.
+    matches: Vec<(usize, Match)>,
+    selected: usize,
+}
+
+/// A line of the picker to draw.
+pub struct Row<'a> {
+    pub text: &'a str,
+    /// Chars of `text` that the query matched.
+    pub positions: &'a [usize],
+    pub selected: bool,
+}
+
+impl Picker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_files(&mut self, root: PathBuf, files: Vec<String>) {
+        self.root = Some(root);
+        self.files = files;
+        self.rank(0..self.files.len());
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn set_query(&mut self, query: &str) {
+        if query == self.query {
+            return;
+        }
+        // Typing more only ever narrows things down
+        let narrowing = query.starts_with(self.query.as_str());
+        self.query = query.to_string();
+        match narrowing {
+            true => {
+                let candidates: Vec<_> = self.matches.iter().map(|&(idx, _)| idx).collect();
+                self.rank(candidates.into_iter())
+            }
+            false => self.rank(0..self.files.len()),
+        }
+    }
+
+    /// Number of files matching the query, and in total.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.matches.len(), self.files.len())
+    }
+
+    /// Moves the selection down the list or up it, going round at the ends.
+    pub fn move_selection(&mut self, direction: Direction) {
+        let len = self.matches.len();
+        if len == 0 {
+            return;
+        }
+        self.selected = match direction {
+            Direction::Forward => (self.selected + 1) % len,
+            Direction::Backward => (self.selected + len - 1) % len,
+        };
+    }
+
+    /// Where the selected file is.
+    pub fn selected(&self) -> Option<PathBuf> {
+        let root = self.root.as_deref()?;
+        let &(idx, _) = self.matches.get(self.selected)?;
+        Some(root.join(Path::new(&self.files[idx])))
+    }
+
+    /// Up to `height` of the matches, scrolled to keep the selection in view.
+    pub fn rows(&self, height: usize) -> impl Iterator<Item = Row<'_>> {
+        let top = (self.selected + 1).saturating_sub(height);
+        self.matches
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(height)
+            .map(|(i, (idx, found))| Row {
+                text: &self.files[*idx],
+                positions: &found.positions,
+                selected: i == self.selected,
+            })
+    }
+
+    /// Scores `candidates` against the query, best first and then shortest first, selecting the
+    /// best.
+    fn rank(&mut self, candidates: impl Iterator<Item = usize>) {
+        let files = &self.files;
+        let query = &self.query;
+        self.matches = candidates
+            .filter_map(|idx| Some((idx, fuzzy_match(query, &files[idx])?)))
+            .collect();
+        self.matches.sort_by_key(|(idx, found)| {
+            (
+                std::cmp::Reverse(found.score),
+                files[*idx].chars().count(),
+                *idx,
+            )
+        });
+        self.selected = 0;
+    }
+}
+
+#[test]
+fn picking_files() {
+    let mut picker = Picker::new();
+    picker.set_query("ed");
+    assert!(picker.is_loading());
+    assert_eq!(picker.selected(), None);
+
+    let files = [
+        "Cargo.toml",
+        "src/editor.rs",
+        "src/editor/document.rs",
+        "src/gui/render.rs",
+    ];
+    picker.set_files(
+        PathBuf::from("root"),
+        files.iter().map(|file| file.to_string()).collect(),
+    );
+    let ranked = |picker: &Picker| {
+        picker
+            .rows(10)
+            .map(|row| row.text.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ranked(&picker),
+        [
+            "src/editor.rs",
+            "src/editor/document.rs",
+            "src/gui/render.rs"
+        ]
+    );
+    assert_eq!(picker.counts(), (3, 4));
+    assert_eq!(
+        picker.selected(),
+        Some(Path::new("root").join("src/editor.rs"))
+    );
+
+    picker.move_selection(Direction::Backward);
+    let rows: Vec<_> = picker.rows(2).collect();
+    assert_eq!(rows[1].text, "src/gui/render.rs");
+    assert!(rows[1].selected);
+    assert_eq!(rows[1].positions, [9, 11]);
+
+    picker.set_query("edd");
+    assert_eq!(ranked(&picker), ["src/editor/document.rs"]);
+    picker.set_query("");
+    assert_eq!(picker.counts(), (4, 4));
+    assert_eq!(
+        picker.selected(),
+        Some(Path::new("root").join("Cargo.toml"))
+    );
+}
@@ -1,9 +1,21 @@
 mod backend;
 
+use super::window::Window;
+use crate::Selections;
 use backend::RenderBackend;
-use raw_window_handle::HasRawWindowHandle;
+use layout::{CharEntry, Layout};
+use std::ops::Range;
 
 mod glyph_atlas;
+mod layout;
+#[cfg(test)]
+mod reference;
+#[cfg(test)]
+mod screenshot;
+
+pub use layout::{Overlay, OverlayLine};
+#[cfg(test)]
+pub use screenshot::Screenshot;
 
 const SHADER_DATA: &[u8] =
     include_bytes!("../shaders/target/spirv-unknown-vulkan1.2/release/deps/shaders.spv.dir/module");
@@ -13,6 +25,10 @@ const SHADER_DATA: &[u8] =
 //     (0, 0, ash::vk::DescriptorType::STORAGE_IMAGE, 1),
 // }
 
+/// Threads along each side of a workgroup of `cs_with_font`, which draws a square of this many
+/// pixels of one glyph. Matches `GROUP_SIZE` in the shaders.
+const GLYPH_GROUP_SIZE: u32 = 8;
+
 backend::define_shader! {
     COMPUTE_SHADER, "cs_with_font", ash::vk::ShaderStageFlags::COMPUTE, SHADER_DATA,
     (0, 0, ash::vk::DescriptorType::STORAGE_IMAGE, 1),
@@ -34,14 +50,6 @@ backend::define_shader! {
     (0, 0, ash::vk::DescriptorType::STORAGE_IMAGE, 1),
 }
 
-#[repr(C)]
-struct CharEntry {
-    atlas_x: u32,
-    atlas_y: u32,
-    pos_x: u32,
-    pos_y: u32,
-}
-
 #[allow(dead_code)]
 pub struct Render {
     backend: RenderBackend,
@@ -58,30 +66,65 @@ pub struct Render {
 
     atlas: glyph_atlas::GlyphAtlas,
     text_buffer: backend::Buffer,
+    layout: Layout,
 }
 
 impl Render {
-    pub fn new(window: &impl HasRawWindowHandle) -> anyhow::Result<Self> {
-        let mut backend = RenderBackend::new(window)?;
+    pub fn new(window: &Window) -> anyhow::Result<Self> {
+        // KAVI_FONT picks another BDF font, like a bigger one for a HiDPI screen
+        let bitmap = match std::env::var_os("KAVI_FONT") {
+            Some(path) => glyph_atlas::AtlasBitmap::load(path)?,
+            None => glyph_atlas::AtlasBitmap::load(glyph_atlas::FONT_PATH)?,
+        };
+        let (width, height) = window.size();
+        Self::with_backend(
+            RenderBackend::new(window, width, height)?,
+            bitmap,
+            width,
+            height,
+        )
+    }
+
+    /// Draws without a window, for reading frames back with `read_frame`.
+    #[cfg(test)]
+    pub fn new_headless(
+        bitmap: glyph_atlas::AtlasBitmap,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        Self::with_backend(
+            RenderBackend::new_headless(width, height)?,
+            bitmap,
+            width,
+            height,
+        )
+    }
 
+    fn with_backend(
+        mut backend: RenderBackend,
+        bitmap: glyph_atlas::AtlasBitmap,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
         let cs = backend.register_shader(&COMPUTE_SHADER);
         let vs = backend.register_shader(&VERTEX_SHADER);
         let fs = backend.register_shader(&FRAGMENT_SHADER);
 
         let descriptor_set = backend.allocate_descriptor_set()?;
 
-        let compute_pipeline = backend.create_compute_pipeline(cs, 0)?;
+        // The glyph size and first glyph, as `Constants` in the shaders
+        let compute_pipeline =
+            backend.create_compute_pipeline(cs, std::mem::size_of::<[u32; 3]>())?;
         let graphics_pipeline = backend.create_graphics_pipeline(vs, fs, 0)?;
-        let storage_image = backend.create_storage_image(1280, 720)?;
+        let storage_image = backend.create_storage_image(width, height)?;
 
         backend::update!(descriptor_set, 0;0 => storage_image);
 
-        let atlas = glyph_atlas::GlyphAtlas::new(&backend)?;
+        let atlas = glyph_atlas::GlyphAtlas::new(&backend, bitmap)?;
         backend::update!(descriptor_set, 1;0 => atlas);
 
-        // let text = TEXT;
-        let buffer =
-            backend.create_storage_buffer((std::mem::size_of::<CharEntry>() * 10000) as u64)?;
+        let layout = Layout::new(width, height, atlas.glyph_size());
+        let buffer = create_text_buffer(&backend, &layout)?;
 
         // for frame in backend.frames() {
         //     frame.cb.record(|cb| {
@@ -103,27 +146,48 @@ impl Render {
             graphics_pipeline,
             storage_image,
             descriptor_set,
+            layout,
             atlas,
             text_buffer: buffer,
         })
     }
 
-    pub fn draw_frame(&mut self, text: &ropey::Rope) -> anyhow::Result<()> {
+    pub fn draw_frame(
+        &mut self,
+        text: &ropey::Rope,
+        selections: &Selections,
+        highlights: &[Range<usize>],
+        overlay: Option<&Overlay>,
+        status: &str,
+    ) -> anyhow::Result<()> {
         let (_, next_image, _) = self.backend.begin_frame()?;
 
-        self.update_buffer(&text)?;
+        let glyphs = self.update_buffer(text, selections, highlights, overlay, status)?;
         let frame = &self.backend.frames()[next_image.index as usize];
 
         let graphics_pipeline = self.backend.graphics_pipeline(self.graphics_pipeline);
         let compute_pipeline = self.backend.compute_pipeline(self.compute_pipeline);
         let render_pass = self.backend.render_pass();
 
+        let (glyph_width, glyph_height) = self.atlas.glyph_size();
+        // Enough workgroups to cover a glyph, for every glyph
+        let groups_x = glyph_width.div_ceil(GLYPH_GROUP_SIZE);
+        let groups_y = glyph_height.div_ceil(GLYPH_GROUP_SIZE);
+        let batches = glyph_batches(glyphs, self.backend.max_workgroups_x());
+
         frame.cb.record(|cb| {
             cb.bind_pipeline(compute_pipeline);
             cb.bind_pipeline(graphics_pipeline);
             cb.bind_descriptor_set(compute_pipeline, &self.descriptor_set);
             cb.bind_descriptor_set(graphics_pipeline, &self.descriptor_set);
-            cb.dispatch(text.len_chars() as u32, 1, 1);
+            for (first, count) in batches {
+                let constants: Vec<u8> = [glyph_width, glyph_height, first]
+                    .iter()
+                    .flat_map(|n| n.to_ne_bytes())
+                    .collect();
+                cb.push_constants(compute_pipeline, &constants);
+                cb.dispatch(count, groups_x, groups_y);
+            }
             cb.with_render_pass(render_pass, &frame.fb, |cb| cb.draw(6, 0));
         })?;
 
@@ -134,44 +198,131 @@ impl Render {
         Ok(())
     }
 
+    /// The last frame drawn, if made with `new_headless`.
+    #[cfg(test)]
+    pub fn read_frame(&self) -> anyhow::Result<Screenshot> {
+        self.backend.read_frame()
+    }
+
+    /// Number of whole lines of text that fit in `height` pixels.
+    pub fn lines_in(&self, height: u32) -> usize {
+        self.layout.lines_in(height)
+    }
+
+    /// Number of lines of text shown, leaving out the status line.
+    pub fn text_lines(&self) -> usize {
+        self.layout.text_lines()
+    }
+
+    /// Number of glyphs that fit across the window.
+    pub fn columns(&self) -> usize {
+        self.layout.columns()
+    }
+
+    /// Scrolls as little as it takes for the line `char_idx` is on to be shown.
+    pub fn scroll_to(&mut self, text: &ropey::Rope, char_idx: usize) {
+        self.layout.scroll_to(text, char_idx);
+    }
+
+    /// The chars of the lines of text on screen.
+    pub fn shown_chars(&self, text: &ropey::Rope) -> Range<usize> {
+        self.layout.shown_chars(text)
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
-        self.backend.recreate_swapchain(width, height)
+        self.layout.resize(width, height);
+        // Waits for the frames in flight, so the old image and buffer are free to go
+        self.backend.recreate_swapchain(width, height)?;
+        self.storage_image = self.backend.create_storage_image(width, height)?;
+        backend::update!(self.descriptor_set, 0;0 => self.storage_image);
+        self.text_buffer = create_text_buffer(&self.backend, &self.layout)?;
+        Ok(())
     }
 
-    fn update_buffer(&mut self, text: &ropey::Rope) -> anyhow::Result<()> {
+    /// Lays out the frame into the buffer the shader reads, returning how many glyphs there
+    /// are to draw. `highlights` are sorted.
+    fn update_buffer(
+        &mut self,
+        text: &ropey::Rope,
+        selections: &Selections,
+        highlights: &[Range<usize>],
+        overlay: Option<&Overlay>,
+        status: &str,
+    ) -> anyhow::Result<u32> {
+        let glyphs = self.layout.glyphs(
+            |c| self.atlas.get(c),
+            text,
+            selections,
+            highlights,
+            overlay,
+            status,
+        );
+        // Mapping nothing isn't allowed
         let chars = self
             .text_buffer
-            .map_memory::<CharEntry>(0, text.chars().count())?;
-
-        let mut x = 0;
-        let mut y = 0;
-        let mut n = 0;
-        for c in text.chars() {
-            let (atlas_x, atlas_y) = match self.atlas.get(c) {
-                Some(e) => e,
-                None => {
-                    if c == '\n' {
-                        y += 1;
-                        x = 0;
-                    }
-                    continue;
-                }
-            };
-
-            chars[n] = CharEntry {
-                atlas_x: atlas_x as u32,
-                atlas_y: atlas_y as u32,
-                pos_x: x,
-                pos_y: y,
-            };
-
-            x += 1;
-            n += 1;
-        }
-
+            .map_memory::<CharEntry>(0, glyphs.len().max(1))?;
+        chars[..glyphs.len()].copy_from_slice(&glyphs);
         self.text_buffer.unmap_memory();
 
         self.descriptor_set.write_buffer(2, 0, &self.text_buffer);
-        Ok(())
+        Ok(glyphs.len() as u32)
+    }
+}
+
+/// Room for a glyph in every cell of the window, which is as many as `Layout` makes.
+fn create_text_buffer(backend: &RenderBackend, layout: &Layout) -> anyhow::Result<backend::Buffer> {
+    // An empty buffer isn't allowed
+    let size = std::mem::size_of::<CharEntry>() * layout.cells().max(1);
+    backend.create_storage_buffer(size as u64)
+}
+
+/// Splits `glyphs` into dispatches of at most `max` glyphs, as the first glyph and how many.
+fn glyph_batches(glyphs: u32, max: u32) -> impl Iterator<Item = (u32, u32)> {
+    (0..glyphs)
+        .step_by(max as usize)
+        .map(move |first| (first, (glyphs - first).min(max)))
+}
+
+// With no GPU, VK_ICD_FILENAMES can point at lavapipe.
+#[test]
+#[ignore = "needs a Vulkan driver"]
+fn headless_frame() {
+    let bitmap = glyph_atlas::AtlasBitmap::load("fonts/creep2-11.bdf").unwrap();
+    let mut render = Render::new_headless(bitmap.clone(), 320, 96).unwrap();
+    let mut reference = reference::ReferenceRender::new(bitmap, 320, 96);
+    let text = ropey::Rope::from_str("fn main() {\n    println!(\"hello\");\n}\n");
+    render
+        .draw_frame(&text, &Selections::default(), &[], None, "status")
+        .unwrap();
+    let frame = render.read_frame().unwrap();
+    assert_eq!((frame.width, frame.height), (320, 96));
+    let first = frame.pixel(0, 0);
+    assert!(frame.pixels.chunks_exact(4).any(|pixel| pixel != first));
+    let expected = reference.draw_frame(&text, &Selections::default(), &[], None, "status");
+    // The GPU may round blends the other way
+    let off = expected
+        .pixels
+        .iter()
+        .zip(&frame.pixels)
+        .position(|(a, b)| a.abs_diff(*b) > 1);
+    if let Some(i) = off {
+        let (x, y) = ((i / 4) as u32 % frame.width, (i / 4) as u32 / frame.width);
+        panic!(
+            "frame differs from the CPU reference at {},{}: {:?} vs {:?}",
+            x,
+            y,
+            frame.pixel(x, y),
+            expected.pixel(x, y),
+        );
     }
 }
+
+#[test]
+fn batches() {
+    assert_eq!(glyph_batches(0, 4).count(), 0);
+    assert_eq!(glyph_batches(4, 4).collect::<Vec<_>>(), [(0, 4)]);
+    assert_eq!(
+        glyph_batches(9, 4).collect::<Vec<_>>(),
+        [(0, 4), (4, 4), (8, 1)]
+    );
+}
@@ -1,6 +1,7 @@
-#![allow(unreachable_code)]
 mod vulkan;
 
+#[cfg(test)]
+use super::Screenshot;
 use anyhow::Context;
 use ash::vk;
 use raw_window_handle::HasRawWindowHandle;
@@ -9,6 +10,8 @@ use vulkan::physical_device::PhysicalDeviceIterExt as _;
 
 const FRAMES_IN_FLIGHT: usize = 1;
 
+const DEBUG: bool = cfg!(debug);
+
 pub struct Frame {
     pub fb: vulkan::Framebuffer,
     pub cb: vulkan::CommandBuffer,
@@ -18,10 +21,10 @@ pub struct Frame {
 #[allow(dead_code)]
 pub struct RenderBackend {
     instance: Arc<vulkan::Instance>,
-    surface: vulkan::Surface,
+    /// `None` when drawing offscreen.
+    surface: Option<vulkan::Surface>,
     device: Arc<vulkan::Device>,
-    // swapchain: vulkan::Swapchain,
-    swapchain: vulkan::DxgiSwapchain,
+    swapchain: vulkan::Presenter,
     render_pass: vulkan::pipeline::RenderPass,
 
     in_flight_fences: Vec<vk::Fence>,
@@ -40,15 +43,10 @@ pub struct RenderBackend {
 pub struct ShaderHandle(usize);
 
 impl RenderBackend {
-    pub fn new(window: &impl HasRawWindowHandle) -> anyhow::Result<Self> {
-        #[cfg(debug)]
-        let debug = true;
-        #[cfg(not(debug))]
-        let debug = false;
-
+    pub fn new(window: &impl HasRawWindowHandle, width: u32, height: u32) -> anyhow::Result<Self> {
         let instance = vulkan::Instance::builder()
             .extensions(ash_window::enumerate_required_extensions(window)?)
-            .debug(debug)
+            .debug(DEBUG)
             .build()?;
         let surface = vulkan::Surface::create(&instance, window)?;
         let pdevice = vulkan::physical_device::enumerate_physical_devices(&instance)?
@@ -56,33 +54,64 @@ impl RenderBackend {
             .next()
             .context("no suitable device found")?;
 
-        let device = vulkan::Device::create(instance.clone(), pdevice, debug)?;
-        // let swapchain = vulkan::Swapchain::new(&instance, &device, &surface)?;
+        let device = vulkan::Device::create(instance.clone(), pdevice, DEBUG, true)?;
 
-        let render_pass =
-            vulkan::pipeline::create_render_pass(&device, vk::Format::B8G8R8A8_UNORM)?;
+        let render_pass = vulkan::pipeline::create_render_pass(
+            &device,
+            vk::Format::B8G8R8A8_UNORM,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
 
-        // let framebuffers = swapchain.framebuffers(&device, &render_pass)?;
-        let mut swapchain = vulkan::dxgi_swapchain(&instance, &device, &window, &surface)?;
-        let framebuffers = swapchain.create_images(&device, &render_pass)?;
+        let extent = vk::Extent2D { width, height };
+        let swapchain = vulkan::Presenter::new(&instance, &device, window, &surface, extent)?;
+        Self::with_presenter(instance, Some(surface), device, render_pass, swapchain)
+    }
 
-        let command_buffer_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(device.command_pool)
-            // .command_buffer_count(swapchain.acquire_semaphores.len() as u32)
-            .command_buffer_count(swapchain.image_count as u32)
-            .level(vk::CommandBufferLevel::PRIMARY);
+    /// Draws into an image rather than a window, without needing one, for reading frames back
+    /// with `read_frame`.
+    #[cfg(test)]
+    pub fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = vulkan::Instance::builder().debug(DEBUG).build()?;
+        let pdevice = vulkan::physical_device::enumerate_physical_devices(&instance)?
+            .find(|pdevice| {
+                pdevice.queue_families.iter().any(|family| {
+                    family
+                        .properties
+                        .queue_flags
+                        .contains(vk::QueueFlags::GRAPHICS)
+                })
+            })
+            .context("no suitable device found")?;
 
-        let command_buffers = unsafe { device.raw.allocate_command_buffers(&command_buffer_info) }?;
+        let device = vulkan::Device::create(instance.clone(), pdevice, DEBUG, false)?;
+
+        let render_pass = vulkan::pipeline::create_render_pass(
+            &device,
+            vk::Format::B8G8R8A8_UNORM,
+            vulkan::OFFSCREEN_LAYOUT,
+        )?;
+
+        let swapchain =
+            vulkan::Presenter::Offscreen(vulkan::Offscreen::new(vk::Extent2D { width, height }));
+        Self::with_presenter(instance, None, device, render_pass, swapchain)
+    }
+
+    fn with_presenter(
+        instance: Arc<vulkan::Instance>,
+        surface: Option<vulkan::Surface>,
+        device: Arc<vulkan::Device>,
+        render_pass: vulkan::pipeline::RenderPass,
+        mut swapchain: vulkan::Presenter,
+    ) -> anyhow::Result<Self> {
+        let framebuffers = swapchain.framebuffers(&device, &render_pass)?;
+        let command_buffers = allocate_command_buffers(&device, framebuffers.len())?;
 
         let frames: Vec<_> = framebuffers
             .into_iter()
             .zip(command_buffers.into_iter())
             .map(|(fb, cb)| Frame {
                 fb,
-                cb: vulkan::CommandBuffer {
-                    raw: cb,
-                    device: Arc::clone(&device),
-                },
+                cb,
                 in_flight: None,
             })
             .collect();
@@ -128,18 +157,19 @@ impl RenderBackend {
         // - Command buffers must be re-recorded before submit
         // - Pretty sure not descriptor sets
 
-        // self.swapchain = self
-        //     .swapchain
-        //     .recreate(&self.surface, vk::Extent2D { width, height })?;
-        // let framebuffers = self
-        //     .swapchain
-        //     .framebuffers(&self.device, &self.render_pass)?;
-        // self.frames
-        let old_frames: Vec<_> = self.frames.drain(..).map(|f| (f.cb, f.in_flight)).collect();
-        self.swapchain.recreate(vk::Extent2D { width, height });
+        let mut old_frames: Vec<_> = self.frames.drain(..).map(|f| (f.cb, f.in_flight)).collect();
+        self.swapchain
+            .recreate(self.surface.as_ref(), vk::Extent2D { width, height })?;
         let framebuffers = self
             .swapchain
-            .create_images(&self.device, &self.render_pass)?;
+            .framebuffers(&self.device, &self.render_pass)?;
+        // A KHR swapchain can come back with more images than it had
+        let missing = framebuffers.len().saturating_sub(old_frames.len());
+        old_frames.extend(
+            allocate_command_buffers(&self.device, missing)?
+                .into_iter()
+                .map(|cb| (cb, None)),
+        );
 
         for i in 0..self.graphics_pipelines.len() {
             let vs = self.graphics_pipelines[i].common.shaders[0].0;
@@ -150,7 +180,7 @@ impl RenderBackend {
                 &[vs, fs],
                 &self.render_pass,
                 &self.descriptor_set_layouts,
-                self.swapchain.extent,
+                self.swapchain.extent(),
                 self.graphics_pipelines[i].common.push_constant_bytes,
             )?;
 
@@ -181,13 +211,14 @@ impl RenderBackend {
 
         let next_image = match self.swapchain.acquire_next_image() {
             Ok(img) => img,
-            Err(_) => {
-                panic!();
-                // self.recreate_swapchain()?;
-                // self.swapchain
-                // .acquire_next_image()
-                // .unwrap()
+            Err(vulkan::SwapchainAcquireImageErr::RecreateSwapchain) => {
+                // The window changed before we were told it did. The swapchain takes the
+                // surface's size where it has one, so the current extent only matters if not.
+                let extent = self.swapchain.extent();
+                self.recreate_swapchain(extent.width, extent.height)?;
+                self.swapchain.acquire_next_image()?
             }
+            Err(e) => return Err(e.into()),
         };
 
         let frame = &mut self.frames[next_image.index as usize];
@@ -208,18 +239,25 @@ impl RenderBackend {
         static mut VALUE: u64 = 1;
 
         let val = unsafe { VALUE };
+        // Offscreen frames don't wait on or signal anything
+        let semaphores = match next_image.acquire_semaphore == vk::Semaphore::null() {
+            true => 0,
+            false => 1,
+        };
+        let values = [val];
         let mut timeline_submit_info = vk::TimelineSemaphoreSubmitInfo::builder()
-            .wait_semaphore_values(std::slice::from_ref(&val))
-            .signal_semaphore_values(std::slice::from_ref(&val));
+            .wait_semaphore_values(&values[..semaphores])
+            .signal_semaphore_values(&values[..semaphores]);
 
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let wait_semaphores = [next_image.acquire_semaphore];
+        let signal_semaphores = [next_image.rendering_finished_semaphore];
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(std::slice::from_ref(&frame.cb.raw))
-            .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-            .wait_semaphores(std::slice::from_ref(&next_image.acquire_semaphore))
+            .wait_dst_stage_mask(&wait_stages[..semaphores])
+            .wait_semaphores(&wait_semaphores[..semaphores])
             // .wait_semaphores(&[])
-            .signal_semaphores(std::slice::from_ref(
-                &next_image.rendering_finished_semaphore,
-            ))
+            .signal_semaphores(&signal_semaphores[..semaphores])
             // .signal_semaphores(&[])
             .push_next(&mut timeline_submit_info)
             // a
@@ -242,6 +280,56 @@ impl RenderBackend {
         Ok(())
     }
 
+    /// Copies back the last frame drawn by a backend made with `new_headless`.
+    #[cfg(test)]
+    pub fn read_frame(&self) -> anyhow::Result<Screenshot> {
+        let index = match &self.swapchain {
+            vulkan::Presenter::Offscreen(offscreen) => offscreen.presented,
+            _ => anyhow::bail!("only frames drawn offscreen can be read back"),
+        }
+        .context("no frame drawn yet")?;
+        let frame = &self.frames[index as usize];
+        if let Some(fence) = frame.in_flight {
+            self.device.wait_for_fence(fence)?;
+        }
+
+        let image = &frame.fb.image;
+        let vk::Extent2D { width, height } = image.extent;
+        let size = width as usize * height as usize * 4;
+        let mut buffer = self.device.create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            size as u64,
+        )?;
+        self.device.one_time_submit(|cb| {
+            // The layout is already right, but drawing has to be done
+            cb.image_barrier(
+                image,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+                vulkan::OFFSCREEN_LAYOUT,
+                vulkan::OFFSCREEN_LAYOUT,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+            );
+            cb.copy_image_to_buffer(image, &buffer);
+        })?;
+
+        // Drawn as BGRA, and the window ignores alpha
+        let pixels = buffer
+            .map_memory::<u8>(0, size)?
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], 255])
+            .collect();
+        buffer.unmap_memory();
+
+        Ok(Screenshot {
+            width,
+            height,
+            pixels,
+        })
+    }
+
     pub fn compute_pipeline(&self, id: usize) -> &ComputePipeline {
         &self.compute_pipelines[id]
     }
@@ -269,7 +357,7 @@ impl RenderBackend {
             &[self.shaders[vs.0], self.shaders[fs.0]],
             &self.render_pass,
             &self.descriptor_set_layouts,
-            self.swapchain.extent,
+            self.swapchain.extent(),
             push_constant_size,
         )?;
 
@@ -352,6 +440,15 @@ impl RenderBackend {
         )
     }
 
+    /// Most workgroups a single dispatch can have along x.
+    pub fn max_workgroups_x(&self) -> u32 {
+        self.device
+            .physical_device
+            .properties
+            .limits
+            .max_compute_work_group_count[0]
+    }
+
     pub fn frames(&self) -> &[Frame] {
         &self.frames
     }
@@ -391,6 +488,28 @@ impl RenderBackend {
     }
 }
 
+fn allocate_command_buffers(
+    device: &Arc<vulkan::Device>,
+    count: usize,
+) -> anyhow::Result<Vec<vulkan::CommandBuffer>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let command_buffer_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(device.command_pool)
+        .command_buffer_count(count as u32)
+        .level(vk::CommandBufferLevel::PRIMARY);
+
+    let command_buffers = unsafe { device.raw.allocate_command_buffers(&command_buffer_info) }?;
+    Ok(command_buffers
+        .into_iter()
+        .map(|raw| vulkan::CommandBuffer {
+            raw,
+            device: Arc::clone(device),
+        })
+        .collect())
+}
+
 impl Drop for RenderBackend {
     fn drop(&mut self) {
         unsafe {
@@ -21,7 +21,17 @@ mod device;
 pub use device::{CommandPool, Device};
 
 mod swapchain;
-pub use swapchain::{dxgi_swapchain, DxgiSwapchain, Framebuffer, Swapchain, SwapchainImage};
+pub use swapchain::{Framebuffer, Presenter, SwapchainAcquireImageErr, SwapchainImage};
+
+#[cfg(test)]
+mod offscreen;
+#[cfg(test)]
+pub use offscreen::{Offscreen, OFFSCREEN_LAYOUT};
+
+#[cfg(windows)]
+mod dxgi;
+#[cfg(windows)]
+pub use dxgi::{dxgi_swapchain, DxgiSwapchain};
 
 pub mod pipeline;
 pub use pipeline::{ComputePipeline, GraphicsPipeline};
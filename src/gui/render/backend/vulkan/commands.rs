@@ -198,6 +198,42 @@ impl RecordingCommandBuffer<'_> {
             );
         }
     }
+
+    /// Copies every pixel of an image in `TRANSFER_SRC_OPTIMAL` into the start of a buffer,
+    /// row after row.
+    #[cfg(test)]
+    pub fn copy_image_to_buffer(&self, image: &Image, buffer: &Buffer) {
+        let subresource_range = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(subresource_range)
+            .image_offset(vk::Offset3D::builder().x(0).y(0).z(0).build())
+            .image_extent(
+                vk::Extent3D::builder()
+                    .width(image.extent.width)
+                    .height(image.extent.height)
+                    .depth(1)
+                    .build(),
+            );
+
+        unsafe {
+            self.0.device.raw.cmd_copy_image_to_buffer(
+                self.0.raw,
+                image.raw,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.raw,
+                std::slice::from_ref(&region),
+            );
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -106,7 +106,7 @@ pub fn create_storage_image(
         let image = device.create_image(
             format,
             vk::Extent2D::builder().width(width).height(height).build(),
-            vk::ImageUsageFlags::STORAGE,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST,
             vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?;
         let view = image.view(image.format)?;
@@ -124,7 +124,7 @@ pub fn create_storage_image(
 
     let image_memory_barrier = vk::ImageMemoryBarrier::builder()
         .src_access_mask(vk::AccessFlags::empty())
-        .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
         .old_layout(vk::ImageLayout::UNDEFINED)
         .new_layout(vk::ImageLayout::GENERAL)
         .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
@@ -133,16 +133,40 @@ pub fn create_storage_image(
         .subresource_range(subresource_range)
         .build();
 
+    // Cleared to black, as only the cells glyphs are drawn to are ever written
+    let cleared_barrier = vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::SHADER_WRITE,
+        old_layout: vk::ImageLayout::GENERAL,
+        ..image_memory_barrier
+    };
+
     device.one_time_submit(|cb| unsafe {
         device.raw.cmd_pipeline_barrier(
             cb.0.raw,
             vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
             vk::DependencyFlags::empty(),
             &[],
             &[],
             &[image_memory_barrier],
         );
+        device.raw.cmd_clear_color_image(
+            cb.0.raw,
+            storage_image.image.raw,
+            vk::ImageLayout::GENERAL,
+            &vk::ClearColorValue::default(),
+            &[subresource_range],
+        );
+        device.raw.cmd_pipeline_barrier(
+            cb.0.raw,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[cleared_barrier],
+        );
     })?;
 
     Ok(storage_image)
@@ -17,16 +17,23 @@ pub struct Queue {
 }
 
 impl Device {
+    /// Leaving out `present` leaves out the extensions for drawing to a window.
     pub fn create(
         instance: Arc<Instance>,
         pdevice: PhysicalDevice,
         validation_layers: bool,
+        present: bool,
     ) -> anyhow::Result<Arc<Self>> {
-        let extensions = vec![
-            vk::KhrSwapchainFn::name().as_ptr(),
-            vk::KhrExternalMemoryFn::name().as_ptr(),
-            vk::KhrExternalMemoryWin32Fn::name().as_ptr(),
-        ];
+        let mut extensions = vec![];
+        if present {
+            extensions.push(vk::KhrSwapchainFn::name().as_ptr());
+            // For presenting through DXGI
+            #[cfg(windows)]
+            extensions.extend([
+                vk::KhrExternalMemoryFn::name().as_ptr(),
+                vk::KhrExternalMemoryWin32Fn::name().as_ptr(),
+            ]);
+        }
         let mut layers = vec![];
 
         if validation_layers {
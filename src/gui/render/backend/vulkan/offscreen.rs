@@ -0,0 +1,60 @@
+use super::{
+    swapchain::{Framebuffer, SwapchainImage},
+    Device,
+};
+use ash::vk;
+use std::sync::Arc;
+
+// Drawing with no window, into an image of our own that stays in `TRANSFER_SRC_OPTIMAL` after
+// each frame so it can be copied back. There's nothing to wait on or signal, so its images come
+// with null semaphores.
+
+pub const OFFSCREEN_LAYOUT: vk::ImageLayout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+
+pub struct Offscreen {
+    pub extent: vk::Extent2D,
+    /// Which framebuffer was drawn to last.
+    pub presented: Option<u32>,
+}
+
+impl Offscreen {
+    pub fn new(extent: vk::Extent2D) -> Self {
+        Self {
+            extent,
+            presented: None,
+        }
+    }
+
+    /// Only ever one, as frames are waited for before the next is started.
+    pub fn framebuffers(
+        &self,
+        device: &Arc<Device>,
+        render_pass: &super::pipeline::RenderPass,
+    ) -> anyhow::Result<Vec<Framebuffer>> {
+        let format = vk::Format::B8G8R8A8_UNORM;
+        let image = device.create_image(
+            format,
+            self.extent,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        Ok(vec![Framebuffer::new(device, render_pass, image, format)?])
+    }
+
+    pub fn acquire_next_image(&self) -> SwapchainImage {
+        SwapchainImage {
+            index: 0,
+            acquire_semaphore: vk::Semaphore::null(),
+            rendering_finished_semaphore: vk::Semaphore::null(),
+        }
+    }
+
+    pub fn present(&mut self, image: SwapchainImage) {
+        self.presented = Some(image.index);
+    }
+
+    pub fn recreate(&mut self, extent: vk::Extent2D) {
+        self.extent = extent;
+        self.presented = None;
+    }
+}
@@ -366,7 +366,12 @@ impl Drop for RenderPass {
     }
 }
 
-pub fn create_render_pass(device: &Arc<Device>, format: vk::Format) -> anyhow::Result<RenderPass> {
+/// `final_layout` is what the image is left in for whatever comes after drawing, like presenting.
+pub fn create_render_pass(
+    device: &Arc<Device>,
+    format: vk::Format,
+    final_layout: vk::ImageLayout,
+) -> anyhow::Result<RenderPass> {
     let attachment_descriptions = [vk::AttachmentDescription::builder()
         .format(format)
         .samples(vk::SampleCountFlags::TYPE_1)
@@ -375,7 +380,7 @@ pub fn create_render_pass(device: &Arc<Device>, format: vk::Format) -> anyhow::R
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .final_layout(final_layout)
         .build()];
 
     let color_attachment_references = [vk::AttachmentReference::builder()
@@ -1,19 +1,32 @@
 use super::backend::{Image, ImageView};
-use std::collections::{hash_map::Entry, HashMap};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    path::Path,
+};
+
+pub const FONT_PATH: &str = "../../../fonts/creep2-11.bdf";
 
 pub struct GlyphAtlas {
     image: Image,
     view: ImageView,
     next_idx: u16,
+    bitmap: AtlasBitmap,
+    // some lru cache mechanism
+}
+
+/// Every glyph of a font drawn into one bitmap, a tile each, as the GPU gets it.
+#[derive(Clone)]
+pub struct AtlasBitmap {
     map: HashMap<char, u16>,
     dims: [u16; 2],
     glyph_dims: [u16; 2],
-    // some lru cache mechanism
+    /// A byte per pixel, row after row: 255 where a glyph is drawn and 0 elsewhere.
+    data: Vec<u8>,
 }
 
-impl GlyphAtlas {
-    pub fn new(ctx: &super::backend::RenderBackend) -> anyhow::Result<Self> {
-        let font = bdf::open("../../../fonts/creep2-11.bdf")?;
+impl AtlasBitmap {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let font = bdf::open(path)?;
 
         let ascent = if let Some(bdf::Property::Integer(x)) = font.properties().get("FONT_ASCENT") {
             *x
@@ -40,10 +53,7 @@ impl GlyphAtlas {
 
         let glyphs_per_line = texture_width / glyph_width;
 
-        let mut staging_buffer =
-            ctx.create_staging_buffer(texture_width as u64 * texture_height as u64)?;
-        let data =
-            staging_buffer.map_memory::<u8>(0, texture_width as usize * texture_height as usize)?;
+        let mut data = vec![0; texture_width as usize * texture_height as usize];
         let mut map = HashMap::new();
 
         let origin_x = 0i32;
@@ -71,6 +81,49 @@ impl GlyphAtlas {
             map.insert(glyph.codepoint(), i as u16);
         }
 
+        Ok(Self {
+            map,
+            dims: [texture_width as u16, texture_height as u16],
+            glyph_dims: [glyph_width as u16, glyph_height as u16],
+            data,
+        })
+    }
+
+    /// Width and height of the whole bitmap.
+    pub fn size(&self) -> (u32, u32) {
+        (self.dims[0] as u32, self.dims[1] as u32)
+    }
+
+    pub fn glyph_size(&self) -> (u32, u32) {
+        (self.glyph_dims[0] as u32, self.glyph_dims[1] as u32)
+    }
+
+    /// What a pixel of the bitmap is, 255 where a glyph is drawn and 0 elsewhere.
+    #[cfg(test)]
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        self.data[(y * self.dims[0] as u32 + x) as usize]
+    }
+
+    pub fn idx_to_coords(&self, idx: u16) -> (u16, u16) {
+        let row_length = self.dims[0] / self.glyph_dims[0];
+        let x = idx % row_length;
+        let y = idx / row_length;
+        (x, y)
+    }
+
+    /// Which tile a char is in, counting in tiles.
+    pub fn get(&self, c: char) -> Option<(u16, u16)> {
+        self.map.get(&c).map(|&idx| self.idx_to_coords(idx))
+    }
+}
+
+impl GlyphAtlas {
+    pub fn new(ctx: &super::backend::RenderBackend, bitmap: AtlasBitmap) -> anyhow::Result<Self> {
+        let (texture_width, texture_height) = bitmap.size();
+        let mut staging_buffer = ctx.create_staging_buffer(bitmap.data.len() as u64)?;
+        staging_buffer
+            .map_memory::<u8>(0, bitmap.data.len())?
+            .copy_from_slice(&bitmap.data);
         staging_buffer.unmap_memory();
 
         let image = ctx.create_destination_image(texture_width, texture_height)?;
@@ -105,17 +158,12 @@ impl GlyphAtlas {
             image,
             view,
             next_idx: 0,
-            map,
-            dims: [texture_width as u16, texture_height as u16],
-            glyph_dims: [glyph_width as u16, glyph_height as u16],
+            bitmap,
         })
     }
 
-    pub fn idx_to_coords(&self, idx: u16) -> (u16, u16) {
-        let row_length = self.dims[0] / self.glyph_dims[0];
-        let x = idx % row_length;
-        let y = idx / row_length;
-        (x, y)
+    pub fn glyph_size(&self) -> (u32, u32) {
+        self.bitmap.glyph_size()
     }
 
     fn write(&self, c: char, idx: u16) {
@@ -132,7 +180,7 @@ impl GlyphAtlas {
     // }
 
     pub fn get(&self, c: char) -> Option<(u16, u16)> {
-        self.map.get(&c).map(|&idx| self.idx_to_coords(idx))
+        self.bitmap.get(c)
     }
 
     // pub fn map(&mut self, c: char) -> u16 {
@@ -0,0 +1,392 @@
+use crate::Selections;
+use std::{iter, ops::Range};
+
+// Where every glyph of a frame goes. This is the part of drawing that doesn't need a GPU, so the
+// reference renderer lays frames out the same way `Render` does.
+
+/// Drawn inverted, for cursors.
+pub const STYLE_CURSOR: u32 = 1;
+/// Drawn on a highlighted background, for selected text.
+pub const STYLE_SELECTED: u32 = 2;
+/// Drawn on a dim background, for search matches.
+pub const STYLE_MATCH: u32 = 3;
+/// Drawn on a panel, for floating windows.
+pub const STYLE_PANEL: u32 = 4;
+/// A selected line of a floating window.
+pub const STYLE_PANEL_SELECTED: u32 = 5;
+/// Picked out in a floating window, like the chars a query matched.
+pub const STYLE_PANEL_MATCH: u32 = 6;
+
+/// A window of text floating over the document, like a picker.
+pub struct Overlay {
+    /// Column and line of the top left corner.
+    pub x: u32,
+    pub y: u32,
+    /// Width in columns. Lines are cut off to fit, and the panel is as tall as the lines.
+    pub width: u32,
+    pub lines: Vec<OverlayLine>,
+}
+
+pub struct OverlayLine {
+    pub text: String,
+    /// Chars of `text` to pick out, sorted.
+    pub highlights: Vec<usize>,
+    pub selected: bool,
+}
+
+/// A rectangle of glyph cells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Area {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Area {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// A glyph to draw: which tile of the atlas, which cell of the window, and how. The same as
+/// `Glyph` in the shaders.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CharEntry {
+    pub atlas_x: u32,
+    pub atlas_y: u32,
+    pub pos_x: u32,
+    pub pos_y: u32,
+    pub style: u32,
+}
+
+/// How many glyphs fit in the window, and what's left to cover up from the last frame.
+pub struct Layout {
+    glyph_size: (u32, u32),
+    /// Lines of text that fit in the window, the last one being the status line.
+    lines: u32,
+    columns: u32,
+    /// The line of text at the top of the window.
+    top_line: usize,
+    /// Which cells were drawn to last frame, row by row. Nothing clears the image between
+    /// frames, so what isn't drawn over again has to be blanked out.
+    drawn: Vec<bool>,
+}
+
+impl Layout {
+    pub fn new(width: u32, height: u32, glyph_size: (u32, u32)) -> Self {
+        let mut layout = Self {
+            glyph_size,
+            lines: 0,
+            columns: 0,
+            top_line: 0,
+            drawn: Vec::new(),
+        };
+        layout.resize(width, height);
+        layout
+    }
+
+    /// Fits the layout to a new image, which starts out blank.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.lines = self.lines_in(height) as u32;
+        self.columns = width / self.glyph_size.0;
+        self.drawn = vec![false; self.cells()];
+    }
+
+    /// Number of whole lines of text that fit in `height` pixels.
+    pub fn lines_in(&self, height: u32) -> usize {
+        (height / self.glyph_size.1) as usize
+    }
+
+    /// Number of lines of text shown, leaving out the status line.
+    pub fn text_lines(&self) -> usize {
+        self.lines.saturating_sub(1) as usize
+    }
+
+    /// Number of glyphs that fit across the window.
+    pub fn columns(&self) -> usize {
+        self.columns as usize
+    }
+
+    /// Number of glyph cells in the window, which is as many glyphs as `glyphs` lays out.
+    pub fn cells(&self) -> usize {
+        (self.lines * self.columns) as usize
+    }
+
+    /// Scrolls as little as it takes for the line `char_idx` is on to be shown.
+    pub fn scroll_to(&mut self, text: &ropey::Rope, char_idx: usize) {
+        let line = text.char_to_line(char_idx.min(text.len_chars()));
+        let shown = self.text_lines().max(1);
+        if line < self.top_line {
+            self.top_line = line;
+        } else if line >= self.top_line + shown {
+            self.top_line = line + 1 - shown;
+        }
+    }
+
+    /// The chars of the lines of text on screen.
+    pub fn shown_chars(&self, text: &ropey::Rope) -> Range<usize> {
+        // The text may have gotten shorter since the last scroll
+        let top = self.top_line.min(text.len_lines() - 1);
+        let bottom = (top + self.text_lines()).min(text.len_lines());
+        text.line_to_char(top)..text.line_to_char(bottom)
+    }
+
+    /// Lays out the text from the line scrolled to, with every cursor, selection and highlighted
+    /// range, the overlay on top of it and the status line, leaving out what's past the right edge. `glyph` is where a
+    /// char is in the atlas, and `highlights` are sorted.
+    pub fn glyphs(
+        &mut self,
+        glyph: impl Fn(char) -> Option<(u16, u16)>,
+        text: &ropey::Rope,
+        selections: &Selections,
+        highlights: &[Range<usize>],
+        overlay: Option<&Overlay>,
+        status: &str,
+    ) -> Vec<CharEntry> {
+        // Not even room for the status line
+        if self.lines == 0 {
+            return Vec::new();
+        }
+        let status_y = self.lines - 1;
+        let area = overlay.map(|overlay| Area {
+            x: overlay.x,
+            y: overlay.y,
+            width: overlay.width.min(self.columns.saturating_sub(overlay.x)),
+            height: (overlay.lines.len() as u32).min(status_y.saturating_sub(overlay.y)),
+        });
+        let columns = self.columns;
+        let mut filled = vec![false; self.cells()];
+        let mut chars = Vec::new();
+        let mut push = |entry: CharEntry| {
+            filled[(entry.pos_y * columns + entry.pos_x) as usize] = true;
+            chars.push(entry);
+        };
+        let space = glyph(' ');
+        let mut ranges = selections.ranges().iter().peekable();
+        let mut highlights = highlights.iter().peekable();
+        let mut x = 0;
+        let mut y = 0;
+        let start = self.shown_chars(text).start;
+        // One more for a cursor at the end of the text
+        let chars_shown = text.chars_at(start).map(Some).chain([None]);
+        for (idx, c) in (start..).zip(chars_shown) {
+            while matches!(ranges.peek(), Some(range) if range.end() < idx) {
+                ranges.next();
+            }
+            while matches!(highlights.peek(), Some(range) if range.end <= idx) {
+                highlights.next();
+            }
+            let style = match ranges.peek() {
+                Some(range) if range.head == idx => STYLE_CURSOR,
+                Some(range) if (range.start()..range.end()).contains(&idx) => STYLE_SELECTED,
+                _ if matches!(highlights.peek(), Some(range) if range.contains(&idx)) => {
+                    STYLE_MATCH
+                }
+                _ => 0,
+            };
+
+            // Line breaks and the end of the text only show up with a cursor or selection on them
+            let tile = match c.and_then(&glyph) {
+                Some(tile) => Some(tile),
+                None if style != 0 => space,
+                None => None,
+            };
+            // Leave the last line for the status
+            if y >= status_y {
+                break;
+            }
+
+            if let Some((atlas_x, atlas_y)) = tile {
+                // Two glyphs in one cell would race, so what's under the overlay is left out
+                if x < columns && !matches!(area, Some(area) if area.contains(x, y)) {
+                    push(CharEntry {
+                        atlas_x: atlas_x as u32,
+                        atlas_y: atlas_y as u32,
+                        pos_x: x,
+                        pos_y: y,
+                        style,
+                    });
+                }
+                x += 1;
+            }
+            if c == Some('\n') {
+                y += 1;
+                x = 0;
+            }
+        }
+
+        if let (Some(overlay), Some(area)) = (overlay, area) {
+            for (line, y) in overlay.lines.iter().zip(area.y..area.y + area.height) {
+                let mut chars_of_line = line.text.chars().map(Some).chain(iter::repeat(None));
+                let mut highlights = line.highlights.iter().peekable();
+                for (i, x) in (area.x..area.x + area.width).enumerate() {
+                    let c = chars_of_line.next().flatten();
+                    while matches!(highlights.peek(), Some(&&idx) if idx < i) {
+                        highlights.next();
+                    }
+                    let style = match highlights.peek() {
+                        Some(&&idx) if idx == i => STYLE_PANEL_MATCH,
+                        _ if line.selected => STYLE_PANEL_SELECTED,
+                        _ => STYLE_PANEL,
+                    };
+                    // The whole panel is drawn, blanks included, to hide the text underneath
+                    let (atlas_x, atlas_y) = c.and_then(&glyph).or(space).unwrap_or_default();
+                    push(CharEntry {
+                        atlas_x: atlas_x as u32,
+                        atlas_y: atlas_y as u32,
+                        pos_x: x,
+                        pos_y: y,
+                        style,
+                    });
+                }
+            }
+        }
+
+        for (x, c) in status.chars().take(columns as usize).enumerate() {
+            if let Some((atlas_x, atlas_y)) = glyph(c) {
+                push(CharEntry {
+                    atlas_x: atlas_x as u32,
+                    atlas_y: atlas_y as u32,
+                    pos_x: x as u32,
+                    pos_y: status_y,
+                    style: 0,
+                });
+            }
+        }
+
+        // Cursors, selections and text that moved away, a status that got shorter, where the
+        // overlay was
+        let (atlas_x, atlas_y) = space.unwrap_or_default();
+        for i in (0..filled.len()).filter(|&i| self.drawn[i] && !filled[i]) {
+            chars.push(CharEntry {
+                atlas_x: atlas_x as u32,
+                atlas_y: atlas_y as u32,
+                pos_x: i as u32 % columns,
+                pos_y: i as u32 / columns,
+                style: 0,
+            });
+        }
+        self.drawn = filled;
+
+        chars
+    }
+}
+
+#[test]
+fn layout() {
+    // Every char in its own tile, on one row
+    let glyph = |c: char| (c.is_ascii_graphic() || c == ' ').then_some((c as u16, 0));
+    let mut layout = Layout::new(50, 33, (5, 11));
+    assert_eq!((layout.text_lines(), layout.columns()), (2, 10));
+
+    let text = ropey::Rope::from_str("ab\nc\nhidden");
+    let at = |chars: &[CharEntry], x, y| {
+        chars
+            .iter()
+            .filter(|entry| (entry.pos_x, entry.pos_y) == (x, y))
+            .map(|entry| (entry.atlas_x as u8 as char, entry.style))
+            .collect::<Vec<_>>()
+    };
+    let chars = layout.glyphs(
+        glyph,
+        &text,
+        &Selections::default(),
+        &[1..2, 3..4],
+        None,
+        "st",
+    );
+    assert_eq!(at(&chars, 0, 0), [('a', STYLE_CURSOR)]);
+    assert_eq!(at(&chars, 1, 0), [('b', STYLE_MATCH)]);
+    // The line break shows up with nothing on it
+    assert_eq!(at(&chars, 2, 0), []);
+    assert_eq!(at(&chars, 0, 1), [('c', STYLE_MATCH)]);
+    // The third line is the status's
+    assert_eq!(at(&chars, 0, 2), [('s', 0)]);
+    assert_eq!(at(&chars, 1, 2), [('t', 0)]);
+    assert_eq!(chars.len(), 5);
+
+    let overlay = Overlay {
+        x: 1,
+        y: 0,
+        width: 2,
+        lines: vec![OverlayLine {
+            text: "xyz".into(),
+            highlights: vec![],
+            selected: true,
+        }],
+    };
+    let chars = layout.glyphs(
+        glyph,
+        &text,
+        &Selections::default(),
+        &[],
+        Some(&overlay),
+        "",
+    );
+    // Cut off to fit, and over the text rather than with it
+    assert_eq!(at(&chars, 1, 0), [('x', STYLE_PANEL_SELECTED)]);
+    assert_eq!(at(&chars, 2, 0), [('y', STYLE_PANEL_SELECTED)]);
+    assert_eq!(at(&chars, 3, 0), []);
+
+    // Where the overlay was gets drawn over, with blanks where there's no text
+    let chars = layout.glyphs(glyph, &text, &Selections::default(), &[], None, "");
+    assert_eq!(at(&chars, 1, 0), [('b', 0)]);
+    assert_eq!(at(&chars, 2, 0), [(' ', 0)]);
+
+    // Nothing past the right edge, so there's never more than a glyph per cell
+    let text = ropey::Rope::from_str("a long line that doesn't fit\n");
+    let overlay = Overlay {
+        x: 8,
+        y: 0,
+        width: 4,
+        lines: vec![OverlayLine {
+            text: "wide".into(),
+            highlights: vec![],
+            selected: false,
+        }],
+    };
+    let status = "a status that doesn't fit either";
+    let chars = layout.glyphs(
+        glyph,
+        &text,
+        &Selections::default(),
+        &[],
+        Some(&overlay),
+        status,
+    );
+    assert!(chars.iter().all(|entry| entry.pos_x < 10));
+    assert_eq!(at(&chars, 9, 0), [('i', STYLE_PANEL)]);
+    assert!(chars.len() <= layout.cells());
+    layout.resize(100, 33);
+    let chars = layout.glyphs(glyph, &text, &Selections::default(), &[], None, status);
+    assert_eq!(at(&chars, 19, 0), [('e', 0)]);
+    assert!(chars.len() <= layout.cells());
+}
+
+#[test]
+fn scrolling() {
+    let glyph = |c: char| c.is_ascii_graphic().then_some((c as u16, 0));
+    let mut layout = Layout::new(50, 33, (5, 11));
+    let text = ropey::Rope::from_str("a\nb\nc\nd\n");
+    let cursor = |idx| Selections::single(crate::Selection::point(idx));
+
+    // Down to the line with the cursor, and no further
+    layout.scroll_to(&text, 4);
+    assert_eq!(layout.shown_chars(&text), 2..6);
+    let chars = layout.glyphs(glyph, &text, &cursor(4), &[], None, "");
+    assert_eq!(chars[0].atlas_x as u8 as char, 'b');
+    assert_eq!((chars[1].pos_y, chars[1].style), (1, STYLE_CURSOR));
+
+    // Still on screen
+    layout.scroll_to(&text, 2);
+    assert_eq!(layout.shown_chars(&text), 2..6);
+    layout.scroll_to(&text, 0);
+    assert_eq!(layout.shown_chars(&text), 0..4);
+
+    // Past the end of text that got shorter
+    layout.scroll_to(&text, 8);
+    let text = ropey::Rope::from_str("a");
+    assert_eq!(layout.shown_chars(&text), 0..1);
+}
@@ -0,0 +1,227 @@
+use super::{
+    glyph_atlas::AtlasBitmap,
+    layout::{
+        CharEntry, Layout, STYLE_CURSOR, STYLE_MATCH, STYLE_PANEL, STYLE_PANEL_MATCH,
+        STYLE_PANEL_SELECTED, STYLE_SELECTED,
+    },
+    Overlay, Screenshot,
+};
+use crate::Selections;
+use std::ops::Range;
+
+// Drawing on the CPU what `cs_with_font` draws on the GPU, so frames can be checked pixel for
+// pixel against it, and layouts tested with no GPU at all. Keep the colors in step with the
+// shader.
+
+/// Foreground and background of a style, as the shader has them.
+fn colors(style: u32) -> ([f32; 3], [f32; 3]) {
+    match style {
+        STYLE_CURSOR => ([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]),
+        STYLE_SELECTED => ([1.0, 1.0, 1.0], [0.2, 0.3, 0.5]),
+        STYLE_MATCH => ([1.0, 1.0, 1.0], [0.4, 0.3, 0.1]),
+        STYLE_PANEL => ([0.9, 0.9, 0.9], [0.12, 0.12, 0.16]),
+        STYLE_PANEL_SELECTED => ([1.0, 1.0, 1.0], [0.2, 0.3, 0.5]),
+        STYLE_PANEL_MATCH => ([1.0, 0.8, 0.3], [0.12, 0.12, 0.16]),
+        _ => ([1.0, 1.0, 1.0], [0.0, 0.0, 0.0]),
+    }
+}
+
+/// Copies the atlas tile of every glyph into its cell of `image`, blending between the colors
+/// of its style by how much of each pixel the glyph covers. Later glyphs draw over earlier ones.
+pub fn draw_glyphs(atlas: &AtlasBitmap, glyphs: &[CharEntry], image: &mut Screenshot) {
+    let (glyph_width, glyph_height) = atlas.glyph_size();
+    for glyph in glyphs {
+        let (fg, bg) = colors(glyph.style);
+        for local_y in 0..glyph_height {
+            for local_x in 0..glyph_width {
+                let x = glyph.pos_x * glyph_width + local_x;
+                let y = glyph.pos_y * glyph_height + local_y;
+                // Writes out of bounds are dropped on the GPU too
+                if x >= image.width || y >= image.height {
+                    continue;
+                }
+                let coverage = atlas.pixel(
+                    glyph.atlas_x * glyph_width + local_x,
+                    glyph.atlas_y * glyph_height + local_y,
+                ) as f32
+                    / 255.0;
+                let start = (y * image.width + x) as usize * 4;
+                let pixel = &mut image.pixels[start..start + 4];
+                for ((channel, fg), bg) in pixel.iter_mut().zip(fg).zip(bg) {
+                    *channel = ((bg + (fg - bg) * coverage) * 255.0).round() as u8;
+                }
+                pixel[3] = u8::MAX;
+            }
+        }
+    }
+}
+
+/// Lays out and draws frames like `Render`, into an image in memory.
+pub struct ReferenceRender {
+    atlas: AtlasBitmap,
+    layout: Layout,
+    image: Screenshot,
+}
+
+impl ReferenceRender {
+    pub fn new(atlas: AtlasBitmap, width: u32, height: u32) -> Self {
+        Self {
+            layout: Layout::new(width, height, atlas.glyph_size()),
+            atlas,
+            image: Screenshot {
+                width,
+                height,
+                pixels: [0, 0, 0, u8::MAX].repeat((width * height) as usize),
+            },
+        }
+    }
+
+    /// Draws over the last frame, as `Render::draw_frame` does, and returns the image.
+    pub fn draw_frame(
+        &mut self,
+        text: &ropey::Rope,
+        selections: &Selections,
+        highlights: &[Range<usize>],
+        overlay: Option<&Overlay>,
+        status: &str,
+    ) -> &Screenshot {
+        let atlas = &self.atlas;
+        let glyphs = self.layout.glyphs(
+            |c| atlas.get(c),
+            text,
+            selections,
+            highlights,
+            overlay,
+            status,
+        );
+        draw_glyphs(&self.atlas, &glyphs, &mut self.image);
+        &self.image
+    }
+}
+
+#[cfg(test)]
+fn test_atlas() -> AtlasBitmap {
+    AtlasBitmap::load("fonts/creep2-11.bdf").unwrap()
+}
+
+#[test]
+fn blit() {
+    let atlas = test_atlas();
+    let (glyph_width, glyph_height) = atlas.glyph_size();
+    let (atlas_x, atlas_y) = atlas.get('A').unwrap();
+    let mut image = Screenshot {
+        width: glyph_width * 2,
+        height: glyph_height,
+        pixels: vec![0; (glyph_width * 2 * glyph_height * 4) as usize],
+    };
+    let glyph = CharEntry {
+        atlas_x: atlas_x as u32,
+        atlas_y: atlas_y as u32,
+        pos_x: 1,
+        pos_y: 0,
+        style: STYLE_CURSOR,
+    };
+    draw_glyphs(&atlas, &[glyph], &mut image);
+
+    let mut lit = 0;
+    for y in 0..glyph_height {
+        // Nothing drawn in the cell to the left
+        for x in 0..glyph_width {
+            assert_eq!(image.pixel(x, y), [0; 4]);
+        }
+        for x in 0..glyph_width {
+            let coverage = atlas.pixel(
+                atlas_x as u32 * glyph_width + x,
+                atlas_y as u32 * glyph_height + y,
+            );
+            // A cursor is inverted: black where the glyph is, white around it
+            let expected = if coverage == u8::MAX { 0 } else { u8::MAX };
+            assert_eq!(
+                image.pixel(glyph_width + x, y),
+                [expected, expected, expected, 255]
+            );
+            lit += (coverage == u8::MAX) as u32;
+        }
+    }
+    assert!(lit > 0);
+}
+
+#[test]
+fn reference_frame() {
+    let atlas = test_atlas();
+    let (glyph_width, glyph_height) = atlas.glyph_size();
+    let mut render = ReferenceRender::new(atlas.clone(), glyph_width * 8, glyph_height * 3);
+    let text = ropey::Rope::from_str("ab\ncd");
+    let frame = render
+        .draw_frame(&text, &Selections::default(), &[0..1, 3..4], None, "ok")
+        .clone();
+
+    // Shows the same pixels as the glyph's tile, colored by its style
+    let cell = |frame: &Screenshot, c: char, pos: (u32, u32), style: u32| {
+        let (atlas_x, atlas_y) = atlas.get(c).unwrap();
+        let (fg, bg) = colors(style);
+        for y in 0..glyph_height {
+            for x in 0..glyph_width {
+                let coverage = atlas.pixel(
+                    atlas_x as u32 * glyph_width + x,
+                    atlas_y as u32 * glyph_height + y,
+                );
+                let color = if coverage == u8::MAX { fg } else { bg };
+                let expected = color.map(|c| (c * 255.0).round() as u8);
+                let pixel = frame.pixel(pos.0 * glyph_width + x, pos.1 * glyph_height + y);
+                assert_eq!(pixel[..3], expected, "{c:?} at {pos:?}");
+            }
+        }
+    };
+    // The default selection is a cursor at the start, and the match under it doesn't show
+    cell(&frame, 'a', (0, 0), STYLE_CURSOR);
+    cell(&frame, 'b', (1, 0), 0);
+    cell(&frame, 'c', (0, 1), STYLE_MATCH);
+    cell(&frame, 'd', (1, 1), 0);
+    cell(&frame, 'o', (0, 2), 0);
+    cell(&frame, 'k', (1, 2), 0);
+    // Untouched cells stay black
+    assert_eq!(frame.pixel(glyph_width * 5, 0), [0, 0, 0, 255]);
+
+    // With an overlay over the text, which gets blanked out again once the overlay goes
+    let overlay = Overlay {
+        x: 1,
+        y: 0,
+        width: 3,
+        lines: vec![super::OverlayLine {
+            text: "xy".into(),
+            highlights: vec![1],
+            selected: false,
+        }],
+    };
+    let with_overlay = render
+        .draw_frame(&text, &Selections::default(), &[], Some(&overlay), "ok")
+        .clone();
+    cell(&with_overlay, 'x', (1, 0), STYLE_PANEL);
+    cell(&with_overlay, 'y', (2, 0), STYLE_PANEL_MATCH);
+    cell(&with_overlay, ' ', (3, 0), STYLE_PANEL);
+
+    let after = render.draw_frame(&text, &Selections::default(), &[], None, "ok");
+    cell(after, 'b', (1, 0), 0);
+    cell(after, ' ', (2, 0), 0);
+    cell(after, ' ', (3, 0), 0);
+}
+
+#[test]
+fn moved_cursor_leaves_nothing_behind() {
+    let atlas = test_atlas();
+    let (glyph_width, glyph_height) = atlas.glyph_size();
+    let (width, height) = (glyph_width * 8, glyph_height * 3);
+    let text = ropey::Rope::from_str("ab\ncd");
+
+    // The cursor on the line break at the end of the first line, then back at the start
+    let mut render = ReferenceRender::new(atlas.clone(), width, height);
+    let at_end = Selections::single(crate::Selection::point(2));
+    render.draw_frame(&text, &at_end, &[2..3], None, "status");
+    let frame = render.draw_frame(&text, &Selections::default(), &[], None, "ok");
+
+    let fresh = ReferenceRender::new(atlas, width, height)
+        .draw_frame(&text, &Selections::default(), &[], None, "ok")
+        .clone();
+    assert!(*frame == fresh, "the last frame shows through");
+}
@@ -0,0 +1,102 @@
+use anyhow::Context;
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+/// A frame read back from the GPU, as rows of RGBA pixels from the top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// For tests, and for looking at what was drawn
+#[allow(dead_code)]
+impl Screenshot {
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let start = (y * self.width + x) as usize * 4;
+        self.pixels[start..start + 4].try_into().unwrap()
+    }
+
+    /// Writes a binary PPM, which leaves out alpha.
+    pub fn write_ppm(&self, mut w: impl Write) -> anyhow::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let rgb: Vec<_> = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| &pixel[..3])
+            .copied()
+            .collect();
+        w.write_all(&rgb)?;
+        Ok(())
+    }
+
+    pub fn write_png(&self, w: impl Write) -> anyhow::Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+
+    /// Reads an 8-bit RGBA PNG, like one `save` wrote, to compare a frame to.
+    pub fn read_png(r: impl Read) -> anyhow::Result<Self> {
+        let mut reader = png::Decoder::new(r).read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+        anyhow::ensure!(
+            info.color_type == png::ColorType::Rgba && info.bit_depth == png::BitDepth::Eight,
+            "expected 8-bit RGBA, found {:?} at {:?}",
+            info.color_type,
+            info.bit_depth
+        );
+        pixels.truncate(info.buffer_size());
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    /// Writes a PNG or a PPM, going by the extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let png = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => true,
+            Some("ppm") => false,
+            _ => anyhow::bail!("can only save a frame as .png or .ppm"),
+        };
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut w = BufWriter::new(file);
+        match png {
+            true => self.write_png(&mut w)?,
+            false => self.write_ppm(&mut w)?,
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn encoding() {
+    let screenshot = Screenshot {
+        width: 2,
+        height: 1,
+        pixels: vec![255, 0, 0, 255, 0, 128, 255, 255],
+    };
+    assert_eq!(screenshot.pixel(1, 0), [0, 128, 255, 255]);
+
+    let mut ppm = Vec::new();
+    screenshot.write_ppm(&mut ppm).unwrap();
+    assert_eq!(ppm, b"P6\n2 1\n255\n\xff\x00\x00\x00\x80\xff");
+
+    let mut png = Vec::new();
+    screenshot.write_png(&mut png).unwrap();
+    assert_eq!(Screenshot::read_png(&png[..]).unwrap(), screenshot);
+
+    assert!(screenshot.save("frame.bmp").is_err());
+}
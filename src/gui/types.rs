@@ -1,7 +1,13 @@
+pub use super::keyboard::Scancode;
+
 #[derive(Debug, Clone)]
 pub struct KeyEvent {
     pub state: KeyState,
+    /// Virtual key, as labelled on the user's layout.
     pub key: Key,
+    /// Physical key position. Shortcuts bind to these.
+    pub scancode: Scancode,
+    /// Text typed by the key.
     pub translated: Option<char>,
     pub mods: Modifiers,
     pub repeat: bool,
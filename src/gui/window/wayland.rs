@@ -0,0 +1,402 @@
+use super::xkb::Keyboard;
+use super::WindowEvent;
+use crate::gui::types::KeyState;
+use anyhow::Context as _;
+use crossbeam_channel::{Receiver, Sender};
+use raw_window_handle::{RawWindowHandle, WaylandHandle};
+use std::{
+    io::{Read, Write},
+    os::unix::{
+        fs::FileExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixStream,
+    },
+    time::{Duration, Instant},
+};
+use wayland_client::{
+    protocol::{wl_compositor::WlCompositor, wl_keyboard, wl_seat, wl_surface::WlSurface},
+    Display, EventQueue, GlobalManager, Main,
+};
+use wayland_protocols::xdg_shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+use xkbcommon::xkb;
+
+// Wayland through libwayland, whose pointers Vulkan needs for a surface. The protocol objects
+// belong to the window's thread, so `close` and `toggle_fullscreen` leave a request on a channel
+// and wake the event loop with a byte down a socket.
+//
+// Unlike X11, Wayland leaves repeating held keys to the client, at the rate the compositor says.
+
+/// Bit of `xdg_toplevel` configure states for being fullscreen.
+const STATE_FULLSCREEN: u32 = xdg_toplevel::State::Fullscreen as u32;
+
+pub struct Window {
+    requests: Sender<Request>,
+    /// Written to after sending a request.
+    wake: UnixStream,
+    display: *mut std::ffi::c_void,
+    surface: *mut std::ffi::c_void,
+}
+
+enum Request {
+    Close,
+    ToggleFullscreen,
+}
+
+struct InitMessage {
+    display: *mut std::ffi::c_void,
+    surface: *mut std::ffi::c_void,
+}
+unsafe impl Send for InitMessage {}
+
+impl Window {
+    pub fn start_with_thread(
+        tx: Sender<WindowEvent>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let (init_tx, init_rx) = crossbeam_channel::bounded(1);
+        let (requests, requests_rx) = crossbeam_channel::unbounded();
+        let (wake, woken) = UnixStream::pair()?;
+        woken.set_nonblocking(true)?;
+
+        std::thread::Builder::new()
+            .name("wayland".to_string())
+            .spawn(move || {
+                let (mut events, mut state) = match EventLoop::new(tx, width, height) {
+                    Ok(created) => created,
+                    Err(e) => {
+                        let _ = init_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = init_tx.send(Ok(InitMessage {
+                    display: events.display.get_display_ptr() as *mut _,
+                    surface: events.surface.as_ref().c_ptr() as *mut _,
+                }));
+                if let Err(e) = events.run(&mut state, requests_rx, woken) {
+                    log::error!("wayland: {:#}", e);
+                }
+                let _ = state.tx.send(WindowEvent::Quit);
+            })?;
+
+        let InitMessage { display, surface } = init_rx.recv()??;
+        Ok(Self {
+            requests,
+            wake,
+            display,
+            surface,
+        })
+    }
+
+    pub fn close(&self) {
+        self.request(Request::Close);
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        self.request(Request::ToggleFullscreen);
+    }
+
+    pub fn raw_window_handle(&self) -> RawWindowHandle {
+        let mut handle = WaylandHandle::empty();
+        handle.display = self.display;
+        handle.surface = self.surface;
+        RawWindowHandle::Wayland(handle)
+    }
+
+    fn request(&self, request: Request) {
+        if self.requests.send(request).is_ok() {
+            let _ = (&self.wake).write_all(&[0]);
+        }
+    }
+}
+
+struct EventLoop {
+    display: Display,
+    queue: EventQueue,
+    surface: Main<WlSurface>,
+    toplevel: Main<xdg_toplevel::XdgToplevel>,
+}
+
+/// What the protocol objects' event handlers share, through `DispatchData`.
+struct State {
+    tx: Sender<WindowEvent>,
+    context: xkb::Context,
+    keyboard: Option<Keyboard>,
+    /// Size the last toplevel configure asked for, applied when the surface configure after it
+    /// comes.
+    pending_size: Option<(u32, u32)>,
+    size: (u32, u32),
+    fullscreen: bool,
+    closed: bool,
+    /// Keys repeated per second, and how long to hold a key before it starts.
+    repeat_rate: u32,
+    repeat_delay: Duration,
+    /// The key being held and when to type it again.
+    repeating: Option<(u32, Instant)>,
+}
+
+impl EventLoop {
+    fn new(tx: Sender<WindowEvent>, width: u32, height: u32) -> anyhow::Result<(Self, State)> {
+        let display = Display::connect_to_env().context("failed to connect to a compositor")?;
+        let mut queue = display.create_event_queue();
+        let attached = display.attach(queue.token());
+        let globals = GlobalManager::new(&attached);
+        queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let compositor = globals.instantiate_exact::<WlCompositor>(4)?;
+        let wm_base = globals.instantiate_exact::<xdg_wm_base::XdgWmBase>(1)?;
+        wm_base.quick_assign(|wm_base, event, _| {
+            if let xdg_wm_base::Event::Ping { serial } = event {
+                wm_base.pong(serial);
+            }
+        });
+        let seat = globals.instantiate_range::<wl_seat::WlSeat>(4, 5)?;
+        seat.quick_assign(|seat, event, _| {
+            if let wl_seat::Event::Capabilities { capabilities } = event {
+                if capabilities.contains(wl_seat::Capability::Keyboard) {
+                    seat.get_keyboard().quick_assign(|_, event, mut data| {
+                        match data.get::<State>() {
+                            Some(state) => state.keyboard_event(event),
+                            None => log::error!("wayland: keyboard event without state"),
+                        }
+                    });
+                }
+            }
+        });
+
+        let surface = compositor.create_surface();
+        let xdg_surface = wm_base.get_xdg_surface(&surface);
+        xdg_surface.quick_assign(|xdg_surface, event, mut data| {
+            if let xdg_surface::Event::Configure { serial } = event {
+                xdg_surface.ack_configure(serial);
+                if let Some(state) = data.get::<State>() {
+                    state.configured();
+                }
+            }
+        });
+        let toplevel = xdg_surface.get_toplevel();
+        toplevel.set_title("kavi".to_string());
+        toplevel.set_app_id("kavi".to_string());
+        toplevel.quick_assign(|_, event, mut data| {
+            if let Some(state) = data.get::<State>() {
+                state.toplevel_event(event);
+            }
+        });
+        surface.commit();
+
+        let mut state = State {
+            tx,
+            context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            keyboard: None,
+            pending_size: None,
+            size: (width, height),
+            fullscreen: false,
+            closed: false,
+            repeat_rate: 25,
+            repeat_delay: Duration::from_millis(600),
+            repeating: None,
+        };
+        let mut events = Self {
+            display,
+            queue,
+            surface,
+            toplevel,
+        };
+        // Wait for the window to be set up before drawing to it
+        events
+            .queue
+            .sync_roundtrip(&mut state, |_, _, _| {})
+            .context("failed to set up the window")?;
+        Ok((events, state))
+    }
+
+    fn run(
+        &mut self,
+        state: &mut State,
+        requests: Receiver<Request>,
+        mut woken: UnixStream,
+    ) -> anyhow::Result<()> {
+        while !state.closed {
+            self.queue.dispatch_pending(state, |_, _, _| {})?;
+            if let Err(e) = self.display.flush() {
+                if e.kind() != std::io::ErrorKind::WouldBlock {
+                    return Err(e.into());
+                }
+            }
+
+            let timeout = match state.repeating {
+                Some((_, at)) => at
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+                    .min(i32::MAX as u128) as i32,
+                None => -1,
+            };
+            let fd = self.display.get_connection_fd();
+            if let Some(guard) = self.queue.prepare_read() {
+                let mut fds = [pollfd(fd), pollfd(woken.as_raw_fd())];
+                if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) } < 0 {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(e.into());
+                    }
+                }
+                match fds[0].revents & libc::POLLIN {
+                    0 => drop(guard),
+                    _ => guard.read_events()?,
+                }
+            }
+
+            // Drained before the requests, so none are missed
+            let mut buf = [0; 64];
+            while matches!(woken.read(&mut buf), Ok(n) if n > 0) {}
+            for request in requests.try_iter() {
+                match request {
+                    Request::Close => state.closed = true,
+                    Request::ToggleFullscreen => match state.fullscreen {
+                        true => self.toplevel.unset_fullscreen(),
+                        false => self.toplevel.set_fullscreen(None),
+                    },
+                }
+            }
+
+            state.repeat_key();
+        }
+
+        self.toplevel.destroy();
+        self.surface.destroy();
+        self.display.flush()?;
+        Ok(())
+    }
+}
+
+impl State {
+    fn keyboard_event(&mut self, event: wl_keyboard::Event) {
+        match event {
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                if format != wl_keyboard::KeymapFormat::XkbV1 {
+                    log::error!("wayland: unsupported keymap format {:?}", format);
+                    return;
+                }
+                match load_keymap(&self.context, fd, size) {
+                    Ok(keymap) => self.keyboard = Some(Keyboard::new(xkb::State::new(&keymap))),
+                    Err(e) => log::error!("wayland: {:#}", e),
+                }
+            }
+            wl_keyboard::Event::Key { key, state, .. } => {
+                let keyboard = match &self.keyboard {
+                    Some(keyboard) => keyboard,
+                    None => return,
+                };
+                let state = match state {
+                    wl_keyboard::KeyState::Pressed => KeyState::Press,
+                    _ => KeyState::Release,
+                };
+                let event = keyboard.event(key, state, false);
+                self.repeating = match state {
+                    KeyState::Press if self.repeat_rate > 0 && keyboard.repeats(key) => {
+                        Some((key, Instant::now() + self.repeat_delay))
+                    }
+                    KeyState::Release if matches!(self.repeating, Some((held, _)) if held != key) => {
+                        self.repeating
+                    }
+                    _ => None,
+                };
+                let _ = self.tx.send(WindowEvent::Keyboard(event));
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                if let Some(keyboard) = &mut self.keyboard {
+                    keyboard.update_mask(mods_depressed, mods_latched, mods_locked, group);
+                }
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                self.repeat_rate = rate.max(0) as u32;
+                self.repeat_delay = Duration::from_millis(delay.max(0) as u64);
+            }
+            wl_keyboard::Event::Leave { .. } => self.repeating = None,
+            _ => {}
+        }
+    }
+
+    /// Types the held key again if it's time.
+    fn repeat_key(&mut self) {
+        let (key, at) = match (self.repeating, &self.keyboard) {
+            (Some(repeating), Some(_)) => repeating,
+            _ => return,
+        };
+        let now = Instant::now();
+        if at > now {
+            return;
+        }
+        let event = self
+            .keyboard
+            .as_ref()
+            .unwrap()
+            .event(key, KeyState::Press, true);
+        let interval = Duration::from_secs(1) / self.repeat_rate.max(1);
+        // Not trying to catch up after falling behind
+        self.repeating = Some((key, (at + interval).max(now)));
+        let _ = self.tx.send(WindowEvent::Keyboard(event));
+    }
+
+    fn toplevel_event(&mut self, event: xdg_toplevel::Event) {
+        match event {
+            xdg_toplevel::Event::Configure {
+                width,
+                height,
+                states,
+            } => {
+                self.fullscreen = states
+                    .chunks_exact(4)
+                    .map(|state| u32::from_ne_bytes([state[0], state[1], state[2], state[3]]))
+                    .any(|state| state == STATE_FULLSCREEN);
+                // Zero leaves it up to us
+                if width > 0 && height > 0 {
+                    self.pending_size = Some((width as u32, height as u32));
+                }
+            }
+            xdg_toplevel::Event::Close => self.closed = true,
+            _ => {}
+        }
+    }
+
+    /// The compositor is done saying how the window should be.
+    fn configured(&mut self) {
+        if let Some(size) = self.pending_size.take() {
+            if size != self.size {
+                self.size = size;
+                let _ = self.tx.send(WindowEvent::Resize(size.0, size.1, None));
+            }
+        }
+        let _ = self.tx.send(WindowEvent::RedrawRequested);
+    }
+}
+
+/// Reads the keymap the compositor shares through a file.
+fn load_keymap(context: &xkb::Context, fd: RawFd, size: u32) -> anyhow::Result<xkb::Keymap> {
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut keymap = vec![0; size as usize];
+    file.read_exact_at(&mut keymap, 0)
+        .context("failed to read keymap")?;
+    let keymap = String::from_utf8(keymap).context("keymap isn't UTF-8")?;
+    xkb::Keymap::new_from_string(
+        context,
+        keymap.trim_end_matches('\0').to_string(),
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .context("invalid keymap")
+}
+
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
@@ -0,0 +1,343 @@
+use super::xkb::Keyboard;
+use super::WindowEvent;
+use crate::gui::types::KeyState;
+use anyhow::Context as _;
+use crossbeam_channel::Sender;
+use raw_window_handle::{RawWindowHandle, XcbHandle};
+use std::sync::Arc;
+use xcb::{x, Xid};
+use xkbcommon::xkb;
+
+// X11 through xcb, which Vulkan can make a surface for. An xcb connection can be used from any
+// thread, so `close` and `toggle_fullscreen` send their requests straight away, and the window's
+// thread only waits for events.
+
+/// What `_NET_WM_STATE` client messages ask for to flip a state.
+const NET_WM_STATE_TOGGLE: u32 = 2;
+
+pub struct Window {
+    conn: Arc<xcb::Connection>,
+    window: x::Window,
+    root: x::Window,
+    atoms: Atoms,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Atoms {
+    wm_protocols: x::Atom,
+    wm_delete_window: x::Atom,
+    net_wm_state: x::Atom,
+    net_wm_state_fullscreen: x::Atom,
+    /// Sent to the window by `close`, like the window manager sends `WM_DELETE_WINDOW`.
+    kavi_close: x::Atom,
+}
+
+impl Atoms {
+    fn intern(conn: &xcb::Connection) -> xcb::Result<Self> {
+        // Ask for them all before waiting for any
+        let cookies: Vec<_> = [
+            "WM_PROTOCOLS",
+            "WM_DELETE_WINDOW",
+            "_NET_WM_STATE",
+            "_NET_WM_STATE_FULLSCREEN",
+            "KAVI_CLOSE",
+        ]
+        .iter()
+        .map(|name| {
+            conn.send_request(&x::InternAtom {
+                only_if_exists: false,
+                name: name.as_bytes(),
+            })
+        })
+        .collect();
+        let mut atoms = cookies
+            .into_iter()
+            .map(|cookie| conn.wait_for_reply(cookie).map(|reply| reply.atom()));
+        let mut next = || atoms.next().unwrap();
+        Ok(Self {
+            wm_protocols: next()?,
+            wm_delete_window: next()?,
+            net_wm_state: next()?,
+            net_wm_state_fullscreen: next()?,
+            kavi_close: next()?,
+        })
+    }
+}
+
+impl Window {
+    pub fn start_with_thread(
+        tx: Sender<WindowEvent>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let (conn, screen) =
+            xcb::Connection::connect(None).context("failed to connect to the X server")?;
+        let conn = Arc::new(conn);
+        let screen = conn
+            .get_setup()
+            .roots()
+            .nth(screen as usize)
+            .context("no such screen")?;
+        let (root, visual, black_pixel) =
+            (screen.root(), screen.root_visual(), screen.black_pixel());
+        let atoms = Atoms::intern(&conn)?;
+
+        let window: x::Window = conn.generate_id();
+        conn.send_request(&x::CreateWindow {
+            depth: x::COPY_FROM_PARENT as u8,
+            wid: window,
+            parent: root,
+            x: 0,
+            y: 0,
+            width: width as u16,
+            height: height as u16,
+            border_width: 0,
+            class: x::WindowClass::InputOutput,
+            visual,
+            value_list: &[
+                x::Cw::BackPixel(black_pixel),
+                x::Cw::EventMask(
+                    x::EventMask::EXPOSURE
+                        | x::EventMask::STRUCTURE_NOTIFY
+                        | x::EventMask::KEY_PRESS
+                        | x::EventMask::KEY_RELEASE
+                        | x::EventMask::FOCUS_CHANGE,
+                ),
+            ],
+        });
+        conn.send_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: x::ATOM_WM_NAME,
+            r#type: x::ATOM_STRING,
+            data: b"kavi",
+        });
+        // Closing the window asks rather than killing the connection
+        conn.send_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: atoms.wm_protocols,
+            r#type: x::ATOM_ATOM,
+            data: &[atoms.wm_delete_window],
+        });
+        conn.send_request(&x::MapWindow { window });
+        conn.flush()?;
+
+        let events_conn = conn.clone();
+        std::thread::Builder::new()
+            .name("x11".to_string())
+            .spawn(move || {
+                // Made here, as the keyboard state can't move between threads
+                let events = EventLoop {
+                    conn: events_conn,
+                    window,
+                    atoms,
+                    tx,
+                    keyboard: None,
+                    size: (width, height),
+                };
+                events.run()
+            })?;
+
+        Ok(Self {
+            conn,
+            window,
+            root,
+            atoms,
+        })
+    }
+
+    pub fn close(&self) {
+        self.send_message(
+            self.window,
+            x::EventMask::NO_EVENT,
+            self.atoms.kavi_close,
+            [0; 5],
+        );
+    }
+
+    /// Asks the window manager to make the window cover the screen, or stop covering it.
+    pub fn toggle_fullscreen(&mut self) {
+        self.send_message(
+            self.root,
+            x::EventMask::SUBSTRUCTURE_NOTIFY | x::EventMask::SUBSTRUCTURE_REDIRECT,
+            self.atoms.net_wm_state,
+            [
+                NET_WM_STATE_TOGGLE,
+                self.atoms.net_wm_state_fullscreen.resource_id(),
+                0,
+                // From a normal application
+                1,
+                0,
+            ],
+        );
+    }
+
+    pub fn raw_window_handle(&self) -> RawWindowHandle {
+        let mut handle = XcbHandle::empty();
+        handle.window = self.window.resource_id();
+        handle.connection = self.conn.get_raw_conn() as *mut _;
+        RawWindowHandle::Xcb(handle)
+    }
+
+    /// Sends a client message about our window to `destination`.
+    fn send_message(
+        &self,
+        destination: x::Window,
+        event_mask: x::EventMask,
+        r#type: x::Atom,
+        data: [u32; 5],
+    ) {
+        let event =
+            x::ClientMessageEvent::new(self.window, r#type, x::ClientMessageData::Data32(data));
+        self.conn.send_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(destination),
+            event_mask,
+            event: &event,
+        });
+        if let Err(e) = self.conn.flush() {
+            log::error!("x11: {}", e);
+        }
+    }
+}
+
+struct EventLoop {
+    conn: Arc<xcb::Connection>,
+    window: x::Window,
+    atoms: Atoms,
+    tx: Sender<WindowEvent>,
+    keyboard: Option<Keyboard>,
+    size: (u32, u32),
+}
+
+impl EventLoop {
+    fn run(mut self) {
+        self.keyboard = match keyboard(&self.conn) {
+            Ok(keyboard) => Some(keyboard),
+            Err(e) => {
+                log::error!("x11: {:#}, keys won't work", e);
+                None
+            }
+        };
+
+        // An event read ahead to check for a repeated key, that still needs handling
+        let mut pending = None;
+        loop {
+            let event = match pending.take() {
+                Some(event) => event,
+                None => match self.conn.wait_for_event() {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::error!("x11: {}", e);
+                        break;
+                    }
+                },
+            };
+
+            match event {
+                xcb::Event::X(x::Event::KeyPress(event)) => {
+                    self.key(event.detail(), KeyState::Press, false)
+                }
+                xcb::Event::X(x::Event::KeyRelease(event)) => {
+                    // A held key repeats as a release and a press with the same time
+                    match self.conn.poll_for_queued_event() {
+                        Ok(Some(xcb::Event::X(x::Event::KeyPress(next))))
+                            if next.detail() == event.detail() && next.time() == event.time() =>
+                        {
+                            self.key(event.detail(), KeyState::Press, true);
+                            continue;
+                        }
+                        Ok(next) => pending = next,
+                        Err(e) => log::error!("x11: {}", e),
+                    }
+                    self.key(event.detail(), KeyState::Release, false);
+                }
+                xcb::Event::X(x::Event::ConfigureNotify(event)) => {
+                    let size = (event.width() as u32, event.height() as u32);
+                    if size != self.size {
+                        self.size = size;
+                        self.send(WindowEvent::Resize(size.0, size.1, None));
+                    }
+                }
+                xcb::Event::X(x::Event::Expose(event)) if event.count() == 0 => {
+                    self.send(WindowEvent::RedrawRequested)
+                }
+                xcb::Event::X(x::Event::MappingNotify(_) | x::Event::FocusIn(_)) => {
+                    // The layout changed, or modifiers went up or down while another window had
+                    // focus and would be stuck as they were
+                    if let Ok(keyboard) = keyboard(&self.conn) {
+                        self.keyboard = Some(keyboard);
+                    }
+                }
+                xcb::Event::X(x::Event::ClientMessage(event)) if self.is_close(&event) => {
+                    self.conn.send_request(&x::DestroyWindow {
+                        window: self.window,
+                    });
+                    let _ = self.conn.flush();
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        self.send(WindowEvent::Quit);
+    }
+
+    /// Whether a client message is the window manager or `close` asking to close the window.
+    fn is_close(&self, event: &x::ClientMessageEvent) -> bool {
+        match event.data() {
+            x::ClientMessageData::Data32([atom, ..])
+                if event.r#type() == self.atoms.wm_protocols =>
+            {
+                atom == self.atoms.wm_delete_window.resource_id()
+            }
+            _ => event.r#type() == self.atoms.kavi_close,
+        }
+    }
+
+    fn key(&mut self, keycode: x::Keycode, state: KeyState, repeat: bool) {
+        let keyboard = match &mut self.keyboard {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+        // X11 key codes are evdev codes plus 8
+        let code = keycode as u32 - 8;
+        let event = keyboard.event(code, state, repeat);
+        if !repeat {
+            keyboard.update_key(code, state);
+        }
+        self.send(WindowEvent::Keyboard(event));
+    }
+
+    fn send(&self, event: WindowEvent) {
+        if self.tx.send(event).is_err() {
+            log::warn!("x11: nobody listening for window events");
+        }
+    }
+}
+
+/// The layout and modifiers of the keyboard, as the X server has them. Modifiers are followed
+/// from key presses after that.
+fn keyboard(conn: &xcb::Connection) -> anyhow::Result<Keyboard> {
+    let (mut major, mut minor, mut base_event, mut base_error) = (0, 0, 0, 0);
+    if !xkb::x11::setup_xkb_extension(
+        conn,
+        xkb::x11::MIN_MAJOR_XKB_VERSION,
+        xkb::x11::MIN_MINOR_XKB_VERSION,
+        xkb::x11::SetupXkbExtensionFlags::NoFlags,
+        &mut major,
+        &mut minor,
+        &mut base_event,
+        &mut base_error,
+    ) {
+        anyhow::bail!("the X server doesn't support XKB");
+    }
+
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let device = xkb::x11::get_core_keyboard_device_id(conn);
+    let keymap =
+        xkb::x11::keymap_new_from_device(&context, conn, device, xkb::KEYMAP_COMPILE_NO_FLAGS);
+    let state = xkb::x11::state_new_from_device(&keymap, conn, device);
+    Ok(Keyboard::new(state))
+}
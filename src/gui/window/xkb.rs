@@ -0,0 +1,215 @@
+use crate::gui::types::{Key, KeyEvent, KeyState, Modifiers, Scancode};
+use xkbcommon::xkb::{self, keysyms};
+
+// Keys on Linux. X11 and Wayland both give evdev codes for which key was pressed, and xkbcommon
+// works out what that means on the user's layout, given the layout and which modifiers are held.
+
+/// xkb key codes are evdev codes plus this, for historical reasons.
+const EVDEV_OFFSET: u32 = 8;
+
+/// Keyboard state for turning evdev codes into key events.
+pub struct Keyboard {
+    state: xkb::State,
+}
+
+impl Keyboard {
+    pub fn new(state: xkb::State) -> Self {
+        Self { state }
+    }
+
+    /// Follows a key going down or up, for when the window system doesn't say what the
+    /// modifiers are.
+    pub fn update_key(&mut self, code: u32, state: KeyState) {
+        let direction = match state {
+            KeyState::Press => xkb::KeyDirection::Down,
+            KeyState::Release => xkb::KeyDirection::Up,
+        };
+        self.state.update_key(code + EVDEV_OFFSET, direction);
+    }
+
+    /// Sets the modifiers and layout group from what the window system says they are.
+    pub fn update_mask(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        self.state
+            .update_mask(depressed, latched, locked, 0, 0, group);
+    }
+
+    /// Whether holding the key down should type it again and again.
+    pub fn repeats(&self, code: u32) -> bool {
+        self.state.get_keymap().key_repeats(code + EVDEV_OFFSET)
+    }
+
+    pub fn event(&self, code: u32, state: KeyState, repeat: bool) -> KeyEvent {
+        let keycode = code + EVDEV_OFFSET;
+        let translated = match state {
+            KeyState::Press => self.state.key_get_utf8(keycode).chars().next(),
+            KeyState::Release => None,
+        };
+        KeyEvent {
+            state,
+            key: keysym_to_key(self.state.key_get_one_sym(keycode)),
+            scancode: Scancode::from_evdev(code),
+            translated,
+            mods: self.modifiers(),
+            repeat,
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        for (name, modifier) in [
+            (xkb::MOD_NAME_CTRL, Modifiers::CONTROL),
+            (xkb::MOD_NAME_ALT, Modifiers::ALT),
+            (xkb::MOD_NAME_SHIFT, Modifiers::SHIFT),
+        ] {
+            if self
+                .state
+                .mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE)
+            {
+                modifiers |= modifier;
+            }
+        }
+        modifiers
+    }
+}
+
+/// The key a keysym is on, as Win32 would name it.
+fn keysym_to_key(keysym: xkb::Keysym) -> Key {
+    const DIGITS: [Key; 10] = [
+        Key::Key0,
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+    ];
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+    const NUMPAD: [Key; 10] = [
+        Key::Numpad0,
+        Key::Numpad1,
+        Key::Numpad2,
+        Key::Numpad3,
+        Key::Numpad4,
+        Key::Numpad5,
+        Key::Numpad6,
+        Key::Numpad7,
+        Key::Numpad8,
+        Key::Numpad9,
+    ];
+    const FUNCTION: [Key; 24] = [
+        Key::F1,
+        Key::F2,
+        Key::F3,
+        Key::F4,
+        Key::F5,
+        Key::F6,
+        Key::F7,
+        Key::F8,
+        Key::F9,
+        Key::F10,
+        Key::F11,
+        Key::F12,
+        Key::F13,
+        Key::F14,
+        Key::F15,
+        Key::F16,
+        Key::F17,
+        Key::F18,
+        Key::F19,
+        Key::F20,
+        Key::F21,
+        Key::F22,
+        Key::F23,
+        Key::F24,
+    ];
+
+    match keysym {
+        keysyms::KEY_0..=keysyms::KEY_9 => DIGITS[(keysym - keysyms::KEY_0) as usize],
+        keysyms::KEY_a..=keysyms::KEY_z => LETTERS[(keysym - keysyms::KEY_a) as usize],
+        keysyms::KEY_A..=keysyms::KEY_Z => LETTERS[(keysym - keysyms::KEY_A) as usize],
+        keysyms::KEY_KP_0..=keysyms::KEY_KP_9 => NUMPAD[(keysym - keysyms::KEY_KP_0) as usize],
+        keysyms::KEY_F1..=keysyms::KEY_F24 => FUNCTION[(keysym - keysyms::KEY_F1) as usize],
+        keysyms::KEY_BackSpace => Key::Backspace,
+        keysyms::KEY_Tab | keysyms::KEY_ISO_Left_Tab => Key::Tab,
+        keysyms::KEY_Clear => Key::Clear,
+        keysyms::KEY_Return | keysyms::KEY_KP_Enter => Key::Return,
+        keysyms::KEY_Pause => Key::Pause,
+        keysyms::KEY_Caps_Lock => Key::Capital,
+        keysyms::KEY_Escape => Key::Escape,
+        keysyms::KEY_space => Key::Space,
+        keysyms::KEY_Prior => Key::Prior,
+        keysyms::KEY_Next => Key::Next,
+        keysyms::KEY_End => Key::End,
+        keysyms::KEY_Home => Key::Home,
+        keysyms::KEY_Left => Key::Left,
+        keysyms::KEY_Up => Key::Up,
+        keysyms::KEY_Right => Key::Right,
+        keysyms::KEY_Down => Key::Down,
+        keysyms::KEY_Select => Key::Select,
+        keysyms::KEY_Print => Key::Print,
+        keysyms::KEY_Execute => Key::Execute,
+        keysyms::KEY_Insert => Key::Insert,
+        keysyms::KEY_Delete => Key::Delete,
+        keysyms::KEY_Help => Key::Help,
+        keysyms::KEY_Super_L => Key::LeftWin,
+        keysyms::KEY_Super_R => Key::RightWin,
+        keysyms::KEY_Menu => Key::Apps,
+        keysyms::KEY_KP_Multiply => Key::Multiply,
+        keysyms::KEY_KP_Add => Key::Add,
+        keysyms::KEY_KP_Separator => Key::Separator,
+        keysyms::KEY_KP_Subtract => Key::Subtract,
+        keysyms::KEY_KP_Decimal => Key::Decimal,
+        keysyms::KEY_KP_Divide => Key::Divide,
+        keysyms::KEY_Num_Lock => Key::NumLock,
+        keysyms::KEY_Scroll_Lock => Key::ScrollLock,
+        keysyms::KEY_Shift_L => Key::LeftShift,
+        keysyms::KEY_Shift_R => Key::RightShfit,
+        keysyms::KEY_Control_L => Key::LeftControl,
+        keysyms::KEY_Control_R => Key::RightControl,
+        keysyms::KEY_Alt_L => Key::LeftAlt,
+        keysyms::KEY_Alt_R | keysyms::KEY_ISO_Level3_Shift => Key::RightAlt,
+        keysyms::KEY_plus | keysyms::KEY_equal => Key::Plus,
+        keysyms::KEY_comma => Key::Comma,
+        keysyms::KEY_minus => Key::Minus,
+        keysyms::KEY_period => Key::Period,
+        keysyms::KEY_slash => Key::Slash,
+        keysyms::KEY_grave => Key::Backtick,
+        keysyms::KEY_bracketleft => Key::LBracket,
+        keysyms::KEY_backslash => Key::Backslash,
+        keysyms::KEY_bracketright => Key::RBracket,
+        keysyms::KEY_apostrophe => Key::Apostrophe,
+        keysyms::KEY_semicolon => Key::Semicolon,
+        keysyms::KEY_numbersign => Key::Hash,
+        _ => Key::Unknown,
+    }
+}
@@ -1,33 +1,445 @@
 #![allow(incomplete_features)]
 #![feature(generic_const_exprs)]
 
+pub mod editor;
 pub mod gui;
 pub mod lsp;
+#[cfg(unix)]
+pub mod server;
 
-#[derive(Debug)]
-pub enum ServerMessage {}
+use anyhow::Context as _;
+use editor::{BufferInfo, DocumentId, Encoding, ReplacedLine, RevisionInfo, SearchStatus};
+use serde::{Deserialize, Serialize};
+use std::{ops::Range, time::Duration};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// Full contents of the current document, sent when a client attaches or switches documents.
+    Snapshot {
+        text: String,
+        selections: Selections,
+    },
+    /// Replace the chars in `range` (indices into the text before the change) with `text`.
+    Delta {
+        range: Range<usize>,
+        text: String,
+    },
+    Selections(Selections),
+    /// Sent after each search command: where the selection is among the matches, or `None`
+    /// once the search is cancelled.
+    Search(Option<SearchStatus>),
+    /// Lines that `Command::ReplacePreview` would change, in order.
+    ReplacePreview(Vec<ReplacedLine>),
+    Status(String),
+    /// A command failed.
+    Error(String),
+    Diagnostics(Vec<Diagnostic>),
+    /// Undo tree of the current document, indexed by revision number.
+    History {
+        revisions: Vec<RevisionInfo>,
+        current: usize,
+    },
+    /// The file behind a document with unsaved changes was changed by something else. The
+    /// client should ask which version to keep and answer with `Command::ResolveExternalChange`.
+    FileChanged {
+        path: String,
+    },
+    /// Unified diff from the file on disk to the buffer.
+    Diff {
+        path: String,
+        diff: String,
+    },
+    /// Every file in the project, as paths relative to `root`, in answer to
+    /// `Command::ListFiles`.
+    Files {
+        root: String,
+        files: Vec<String>,
+    },
+    /// Open documents in the order they were opened, sent whenever the list or the current
+    /// document changes.
+    Buffers {
+        buffers: Vec<BufferInfo>,
+        current: DocumentId,
+    },
+    /// Quitting would lose unsaved changes. The client should ask, and send `Quit { force: true }`
+    /// to go ahead.
+    ConfirmQuit {
+        unsaved: Vec<BufferInfo>,
+    },
+    /// The editor is done; clients should close.
+    Quit,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
     Command(Command),
+    /// The client is going away. The server keeps running for other clients.
     Shutdown,
+    /// Stop the server, detaching every client.
+    Kill,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
-    Open(String),
+    /// Opens a file, or switches to it if it's already open, with the cursor at a line and
+    /// column (in chars, counting from 0) if given.
+    Open {
+        path: String,
+        position: Option<(usize, usize)>,
+    },
+    ReopenWithEncoding(Encoding),
+    Save,
+    SaveAs(String),
+    ResolveExternalChange {
+        path: String,
+        resolution: Resolution,
+    },
+    NextBuffer,
+    PreviousBuffer,
+    /// Switches to the document with the given id, file name or path.
+    SwitchBuffer(String),
+    /// Closes the current document. Without `force`, refuses if it has unsaved changes.
+    CloseBuffer {
+        force: bool,
+    },
+    Buffers,
+    /// Without `force`, asks first if any document has unsaved changes.
+    Quit {
+        force: bool,
+    },
     Select(Scope),
+    /// Moves the cursors, collapsing the selections.
+    Move(Motion),
+    /// Selects from the cursor to where moving it this many times would take it.
+    SelectTo(Motion, usize),
+    /// Number of lines the client shows, which is how far a page moves.
+    SetPageHeight(usize),
+    /// Grows a selection of whole lines by one line, or selects the current line.
+    GrowLines(Direction),
+    /// Adds a cursor on the line below the last selection or above the first.
+    AddCursor(Direction),
+    /// Splits selections spanning several lines into one per line.
+    SplitLines,
+    /// Replaces the selections with the matches of a regex inside them.
+    SelectMatches(String),
+    /// Drops every selection but the primary one.
+    KeepPrimary,
     Insert(char),
+    /// Removes the selection, or the character before the cursor.
     Delete,
+    /// Removes the selection, or the character under the cursor.
+    DeleteSelection,
+    DeleteWordBackward,
 
     NewLine,
+    /// Starts a new line below or above the cursor's line.
+    OpenLine(Direction),
+
+    /// Selects the next match of `pattern`, searching from the cursor.
+    Search {
+        pattern: String,
+        direction: Direction,
+    },
+    /// Selects the next match of a pattern still being typed. The search starts from where the
+    /// cursor was before the first preview, and goes back there on `CancelSearch`.
+    SearchPreview {
+        pattern: String,
+        direction: Direction,
+    },
+    CancelSearch,
+    /// Replaces every match of the regex `pattern` in the document with `replacement`, in which
+    /// `$1` or `${name}` stand for what a group captured. Undone as one change.
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Asks which lines `Replace` would change, without changing them.
+    ReplacePreview {
+        pattern: String,
+        replacement: String,
+    },
+    /// Repeats the last search, the same way or the opposite way.
+    SearchNext(Direction),
+    /// Searches every file in the project for a regex in the background, listing the lines
+    /// found in a results buffer as they come in.
+    Grep(String),
+    /// Opens the result on the cursor's line of the results buffer.
+    OpenResult,
+    /// Asks for every file in the project, leaving out ignored ones.
+    ListFiles,
 
     Undo,
     Redo,
+    Earlier(Step),
+    Later(Step),
+    Revision(usize),
+    History,
+}
+
+impl Command {
+    /// Whether the command changes the text of the current document.
+    pub fn edits(&self) -> bool {
+        matches!(
+            self,
+            Self::Insert(_)
+                | Self::Delete
+                | Self::DeleteSelection
+                | Self::DeleteWordBackward
+                | Self::NewLine
+                | Self::OpenLine(_)
+                | Self::Replace { .. }
+        )
+    }
+}
+
+/// What to do about a file that changed on disk underneath unsaved changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Resolution {
+    /// Throw away the buffer's changes and load the file again. Can be undone.
+    Reload,
+    /// Keep the buffer, overwriting the file on the next save.
+    Keep,
+    /// Show how the buffer differs from the file, and keep asking.
+    Diff,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Forward,
+    Backward,
 }
 
-#[derive(Debug)]
+impl Direction {
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::Forward => Self::Backward,
+            Self::Backward => Self::Forward,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Scope {
     Character,
     Line,
+    /// The word under the cursor and the whitespace after it.
+    Word,
+    /// The paragraph under the cursor and the blank lines after it.
+    Paragraph,
+    /// Inside the innermost pair of brackets around the selection, then the brackets too.
+    Brackets,
+    /// Inside the quoted string around the selection, then the quotes too.
+    Quotes,
+    /// The lines around the cursor indented at least as far as its line.
+    Indent,
+    /// The whole document.
+    Buffer,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Motion {
+    Left,
+    Right,
+    /// Up and down keep to the column the cursor was in before moving vertically, even across
+    /// shorter lines.
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    LineStart,
+    LineEnd,
+    ParagraphForward,
+    ParagraphBackward,
+    DocumentStart,
+    DocumentEnd,
+    PageUp,
+    PageDown,
+}
+
+/// How far to move through undo history.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Step {
+    Revisions(usize),
+    Time(Duration),
+}
+
+impl std::str::FromStr for Step {
+    type Err = anyhow::Error;
+
+    /// Parses a count of revisions (`"3"`) or a span of time (`"5m"`, `"5 minutes"`, `"1h"`).
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let count: u64 = count.parse()?;
+        let seconds = match unit.trim() {
+            "" => return Ok(Self::Revisions(count as usize)),
+            "s" | "sec" | "second" | "seconds" => 1,
+            "m" | "min" | "minute" | "minutes" => 60,
+            "h" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            unit => anyhow::bail!("unknown unit of time `{}`", unit),
+        };
+        let seconds = count.checked_mul(seconds).context("too far back")?;
+        Ok(Self::Time(Duration::from_secs(seconds)))
+    }
+}
+
+/// Char indices into a document. The cursor sits at `head`; when `anchor == head` nothing is
+/// selected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn point(idx: usize) -> Self {
+        Self {
+            anchor: idx,
+            head: idx,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    /// The same range with the cursor moved by `f`, and the anchor too.
+    pub fn map(self, mut f: impl FnMut(usize) -> usize) -> Self {
+        Self {
+            anchor: f(self.anchor),
+            head: f(self.head),
+        }
+    }
+}
+
+/// Every selection in a document, sorted by where they start and not overlapping. Edits apply to
+/// all of them; the primary one is what the view follows and what commands that only make sense
+/// for one cursor, like searching, start from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selections {
+    ranges: Vec<Selection>,
+    primary: usize,
+}
+
+impl Default for Selections {
+    fn default() -> Self {
+        Self::single(Selection::default())
+    }
+}
+
+impl Selections {
+    pub fn single(selection: Selection) -> Self {
+        Self {
+            ranges: vec![selection],
+            primary: 0,
+        }
+    }
+
+    /// Sorts `ranges` and merges any that overlap. `primary` is an index into `ranges`.
+    pub fn new(ranges: Vec<Selection>, primary: usize) -> Self {
+        assert!(primary < ranges.len(), "no primary selection");
+        let mut order: Vec<_> = (0..ranges.len()).collect();
+        order.sort_by_key(|&idx| (ranges[idx].start(), ranges[idx].end()));
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(ranges.len());
+        let mut new_primary = 0;
+        for idx in order {
+            let range = ranges[idx];
+            match merged.last_mut() {
+                Some(last) if range.start() < last.end() || range.start() == last.start() => {
+                    let (start, end) = (last.start(), last.end().max(range.end()));
+                    // Keep the direction of the primary selection if it's one of them
+                    let backward = match idx == primary {
+                        true => range.head < range.anchor,
+                        false => last.head < last.anchor,
+                    };
+                    *last = match backward {
+                        true => Selection {
+                            anchor: end,
+                            head: start,
+                        },
+                        false => Selection {
+                            anchor: start,
+                            head: end,
+                        },
+                    };
+                }
+                _ => merged.push(range),
+            }
+            if idx == primary {
+                new_primary = merged.len() - 1;
+            }
+        }
+
+        Self {
+            ranges: merged,
+            primary: new_primary,
+        }
+    }
+
+    pub fn primary(&self) -> Selection {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    pub fn ranges(&self) -> &[Selection] {
+        &self.ranges
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The selections with `f` applied to each, merging any that end up overlapping.
+    pub fn map(&self, f: impl FnMut(Selection) -> Selection) -> Self {
+        Self::new(self.ranges.iter().copied().map(f).collect(), self.primary)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub range: Range<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+#[test]
+fn parse_step() {
+    assert_eq!("3".parse::<Step>().unwrap(), Step::Revisions(3));
+    assert_eq!(
+        "5 minutes".parse::<Step>().unwrap(),
+        Step::Time(Duration::from_secs(300))
+    );
+    assert_eq!(
+        "10s".parse::<Step>().unwrap(),
+        Step::Time(Duration::from_secs(10))
+    );
+    assert!("5 fortnights".parse::<Step>().is_err());
+    assert!("minutes".parse::<Step>().is_err());
+    assert!("99999999999999999 days".parse::<Step>().is_err());
 }
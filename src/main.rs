@@ -1,5 +1,10 @@
 // use kavi::gui::{self, Key, KeyEvent, Window, WindowEvent};
-use kavi::{gui, lsp, ClientMessage};
+#[cfg(unix)]
+use kavi::server;
+use kavi::{
+    editor::{Editor, CHECK_FILES_INTERVAL},
+    gui, lsp, ClientMessage,
+};
 
 mod logging {
     use log::{Level, Metadata, Record};
@@ -38,9 +43,24 @@ mod logging {
 fn main() -> anyhow::Result<()> {
     logging::init(log::LevelFilter::Trace);
 
+    #[cfg(unix)]
+    {
+        let args: Vec<_> = std::env::args().skip(1).collect();
+        match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["--server", path] => return serve(path),
+            ["--connect", path] => return attach(path),
+            _ => {}
+        }
+    }
+
     // let (mut lsp, lsp_rx) = lsp::start()?;
     // lsp.client.initialize()?;
     let gui = gui::spawn()?;
+    let mut editor = Editor::new();
+    gui.tx.send(editor.snapshot())?;
+    gui.tx.send(editor.buffer_list())?;
+    let check_files = crossbeam_channel::tick(CHECK_FILES_INTERVAL);
+    let grep_events = editor.grep_events();
 
     loop {
         crossbeam_channel::select! {
@@ -50,8 +70,22 @@ fn main() -> anyhow::Result<()> {
             recv(gui.rx) -> msg => {
                 log::info!("from gui: {:?}", msg);
                 match msg.unwrap() {
-                    ClientMessage::Shutdown => break,
-                    _ => {}
+                    ClientMessage::Command(command) => {
+                        for reply in editor.execute(command) {
+                            gui.tx.send(reply)?;
+                        }
+                    }
+                    ClientMessage::Shutdown | ClientMessage::Kill => break,
+                }
+            }
+            recv(check_files) -> _ => {
+                for reply in editor.check_files() {
+                    gui.tx.send(reply)?;
+                }
+            }
+            recv(grep_events) -> event => {
+                for reply in editor.handle_grep(event?) {
+                    gui.tx.send(reply)?;
                 }
             }
         }
@@ -60,3 +94,40 @@ fn main() -> anyhow::Result<()> {
     // lsp.client.shutdown()?;
     Ok(())
 }
+
+/// Runs a headless editor that clients attach to with `--connect`.
+#[cfg(unix)]
+fn serve(path: &str) -> anyhow::Result<()> {
+    let mut server = server::listen(path)?;
+    let mut editor = Editor::new();
+    server.run(&mut editor)
+}
+
+/// Runs a GUI client for the editor server listening on `path`.
+#[cfg(unix)]
+fn attach(path: &str) -> anyhow::Result<()> {
+    let connection = server::connect(path)?;
+    let gui = gui::spawn()?;
+
+    loop {
+        crossbeam_channel::select! {
+            recv(connection.rx) -> msg => match msg {
+                Ok(msg) => gui.tx.send(msg)?,
+                Err(_) => {
+                    log::error!("lost connection to server");
+                    break;
+                }
+            },
+            recv(gui.rx) -> msg => {
+                let msg = msg?;
+                let detach = matches!(msg, ClientMessage::Shutdown | ClientMessage::Kill);
+                connection.tx.send(msg)?;
+                if detach {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,290 @@
+mod transport;
+
+use crate::{
+    editor::{Editor, CHECK_FILES_INTERVAL},
+    ClientMessage, ServerMessage,
+};
+use anyhow::Context as _;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use std::{
+    collections::HashMap,
+    io::{BufReader, Write as _},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+// Out-of-process mode: one long-running editor server owns the documents, any number of GUI
+// clients attach to it over a unix socket. Clients coming and going (or crashing) doesn't affect
+// the session; only `ClientMessage::Kill` stops the server.
+
+type ClientId = usize;
+
+/// How many encoded batches of messages can wait to be written to a client. One that falls this
+/// far behind is dropped rather than holding up the editor or the other clients.
+const CLIENT_QUEUE: usize = 256;
+
+enum Event {
+    Connected(ClientId, Sender<Vec<u8>>),
+    Message(ClientId, ClientMessage),
+    Disconnected(ClientId),
+}
+
+pub struct Server {
+    path: PathBuf,
+    rx: Receiver<Event>,
+    clients: HashMap<ClientId, Sender<Vec<u8>>>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub fn listen(path: impl AsRef<Path>) -> anyhow::Result<Server> {
+    let path = path.as_ref();
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            anyhow::bail!("a server is already listening on {}", path.display());
+        }
+        // Left behind by a server that didn't shut down cleanly
+        std::fs::remove_file(path)?;
+    }
+
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    std::thread::Builder::new()
+        .name("server listener".to_string())
+        .spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = accept(id, stream, tx.clone()) {
+                            log::error!("server: failed to accept client {} ({})", id, e);
+                        }
+                    }
+                    Err(e) => log::error!("server: failed to accept client ({})", e),
+                }
+            }
+        })
+        .context("failed to spawn server listener thread")?;
+
+    Ok(Server {
+        path: path.to_owned(),
+        rx,
+        clients: HashMap::new(),
+    })
+}
+
+fn accept(id: ClientId, mut stream: UnixStream, tx: Sender<Event>) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (queue_tx, queue_rx) = crossbeam_channel::bounded::<Vec<u8>>(CLIENT_QUEUE);
+
+    std::thread::Builder::new()
+        .name(format!("client {} writer", id))
+        .spawn(move || {
+            for buf in queue_rx {
+                if let Err(e) = stream.write_all(&buf).and_then(|()| stream.flush()) {
+                    log::debug!("server: failed to write to client {} ({})", id, e);
+                    break;
+                }
+            }
+            // The server dropped the client, or it stopped reading. Unblocks the reader too
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        })?;
+
+    tx.send(Event::Connected(id, queue_tx))?;
+
+    std::thread::Builder::new()
+        .name(format!("client {}", id))
+        .spawn(move || {
+            loop {
+                match transport::recv(&mut reader) {
+                    Ok(msg) => {
+                        if tx.send(Event::Message(id, msg)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        log::debug!("server: client {} disconnected ({})", id, e);
+                        break;
+                    }
+                }
+            }
+            let _ = tx.send(Event::Disconnected(id));
+        })?;
+
+    Ok(())
+}
+
+impl Server {
+    pub fn run(&mut self, editor: &mut Editor) -> anyhow::Result<()> {
+        let check_files = crossbeam_channel::tick(CHECK_FILES_INTERVAL);
+        let grep_events = editor.grep_events();
+        loop {
+            let event = crossbeam_channel::select! {
+                recv(self.rx) -> event => event?,
+                recv(check_files) -> _ => {
+                    let replies = editor.check_files();
+                    self.broadcast(&replies)?;
+                    continue;
+                }
+                recv(grep_events) -> event => {
+                    let replies = editor.handle_grep(event?);
+                    self.broadcast(&replies)?;
+                    continue;
+                }
+            };
+
+            match event {
+                Event::Connected(id, queue) => {
+                    log::info!("server: client {} attached", id);
+                    let mut state = transport::encode(&editor.snapshot())?;
+                    state.extend(transport::encode(&editor.buffer_list())?);
+                    // Nothing is queued yet, so this can only fail if the writer gave up already
+                    if queue.try_send(state).is_ok() {
+                        self.clients.insert(id, queue);
+                    }
+                }
+                Event::Message(id, ClientMessage::Command(command)) => {
+                    let replies = editor.execute(command);
+                    self.broadcast(&replies)?;
+                    if replies.iter().any(|msg| matches!(msg, ServerMessage::Quit)) {
+                        log::info!("server: quit by client {}", id);
+                        return Ok(());
+                    }
+                }
+                Event::Message(id, ClientMessage::Shutdown) | Event::Disconnected(id) => {
+                    if self.clients.remove(&id).is_some() {
+                        log::info!("server: client {} detached", id);
+                    }
+                }
+                Event::Message(id, ClientMessage::Kill) => {
+                    log::info!("server: killed by client {}", id);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn broadcast(&mut self, messages: &[ServerMessage]) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        for msg in messages {
+            buf.extend(transport::encode(msg)?);
+        }
+
+        self.clients
+            .retain(|id, queue| match queue.try_send(buf.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("server: dropped client {} for falling behind", id);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    log::warn!("server: lost client {}", id);
+                    false
+                }
+            });
+
+        Ok(())
+    }
+}
+
+/// A client's end of the socket, as channels.
+pub struct Connection {
+    pub tx: Sender<ClientMessage>,
+    pub rx: Receiver<ServerMessage>,
+}
+
+pub fn connect(path: impl AsRef<Path>) -> anyhow::Result<Connection> {
+    let path = path.as_ref();
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let (tx, client_rx) = crossbeam_channel::unbounded::<ClientMessage>();
+    let (client_tx, rx) = crossbeam_channel::unbounded::<ServerMessage>();
+
+    std::thread::Builder::new()
+        .name("connection reader".to_string())
+        .spawn(move || loop {
+            match transport::recv(&mut reader) {
+                Ok(msg) => {
+                    if client_tx.send(msg).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("client: server closed connection ({})", e);
+                    break;
+                }
+            }
+        })?;
+
+    std::thread::Builder::new()
+        .name("connection writer".to_string())
+        .spawn(move || {
+            for msg in client_rx {
+                if let Err(e) = transport::send(&mut stream, &msg) {
+                    log::warn!("client: failed to send to server ({})", e);
+                    break;
+                }
+            }
+            // Unblocks the reader too
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        })?;
+
+    Ok(Connection { tx, rx })
+}
+
+#[test]
+fn headless_client() {
+    use crate::Command;
+    use std::time::Duration;
+
+    let timeout = Duration::from_secs(5);
+    let path = std::env::temp_dir().join(format!("kavi-test-{}.sock", std::process::id()));
+
+    let mut server = listen(&path).unwrap();
+    let thread = std::thread::spawn(move || server.run(&mut Editor::new()).unwrap());
+
+    let client = connect(&path).unwrap();
+    match client.rx.recv_timeout(timeout).unwrap() {
+        ServerMessage::Snapshot { text, .. } => assert_eq!(text, ""),
+        msg => panic!("expected snapshot, got {:?}", msg),
+    }
+    match client.rx.recv_timeout(timeout).unwrap() {
+        ServerMessage::Buffers { buffers, current } => assert_eq!(buffers[0].id, current),
+        msg => panic!("expected buffers, got {:?}", msg),
+    }
+
+    client
+        .tx
+        .send(ClientMessage::Command(Command::Insert('a')))
+        .unwrap();
+    match client.rx.recv_timeout(timeout).unwrap() {
+        ServerMessage::Delta { range, text } => {
+            assert_eq!(range, 0..0);
+            assert_eq!(text, "a");
+        }
+        msg => panic!("expected delta, got {:?}", msg),
+    }
+
+    // Dropping the client without saying goodbye shouldn't lose the session
+    drop(client);
+
+    let client = connect(&path).unwrap();
+    match client.rx.recv_timeout(timeout).unwrap() {
+        ServerMessage::Snapshot { text, selections } => {
+            assert_eq!(text, "a");
+            assert_eq!(selections.primary().head, 1);
+        }
+        msg => panic!("expected snapshot, got {:?}", msg),
+    }
+
+    client.tx.send(ClientMessage::Kill).unwrap();
+    thread.join().unwrap();
+    assert!(!path.exists());
+}
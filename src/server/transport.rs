@@ -0,0 +1,51 @@
+use anyhow::Context as _;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{BufRead, Write};
+
+// Same framing as the language server protocol: a `Content-Length` header, a blank line, then
+// the message as json.
+
+pub fn encode(message: &impl Serialize) -> anyhow::Result<Vec<u8>> {
+    let text = serde_json::to_string(message)?;
+    let mut buf = format!("Content-Length: {}\r\n\r\n", text.len()).into_bytes();
+    buf.extend_from_slice(text.as_bytes());
+    Ok(buf)
+}
+
+pub fn send<W: Write>(writer: &mut W, message: &impl Serialize) -> anyhow::Result<()> {
+    writer.write_all(&encode(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn recv<R, T>(reader: &mut R) -> anyhow::Result<T>
+where
+    R: BufRead,
+    T: DeserializeOwned,
+{
+    let mut buf = String::new();
+    let mut size = None;
+    loop {
+        buf.clear();
+
+        if reader.read_line(&mut buf)? == 0 {
+            anyhow::bail!("stream closed");
+        }
+
+        let header = buf.trim();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(("Content-Length", value)) = header.split_once(": ") {
+            size = Some(value.parse::<usize>().context("invalid content length")?);
+        }
+    }
+
+    let size = size.context("missing content length")?;
+
+    let mut buf = buf.into_bytes();
+    buf.resize(size, 0);
+    reader.read_exact(&mut buf)?;
+    Ok(serde_json::from_slice(&buf)?)
+}